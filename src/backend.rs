@@ -1,155 +1,452 @@
+pub mod cli;
+pub mod native;
+pub mod recording;
+
+use crate::crop::Crop;
+use crate::error::{Error, Result};
+use crate::filtergraph::{Deinterlace, Device, Filter, FilterGraph};
+use crate::frontend::Disposition;
+use crate::frontend::FieldOrder;
+use crate::frontend::MasteringDisplay;
 use crate::frontend::Stream;
 use crate::frontend::StreamMappings;
-use crate::interface::Opt;
-use crate::interface::TVOptions;
+use crate::frontend::Video;
+use crate::interlace::ScanType;
+use crate::options::Libx264Tune;
+use crate::options::Options;
+use crate::options::TVOptions;
+use crate::policy::Target;
+use crate::progress::FileProgress;
 
 use ffmpeg::codec;
 use log::error;
+use log::info;
 use log::trace;
-use simple_error::SimpleError;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::ffi::OsString;
 use std::iter::Iterator;
 use std::path::Path;
+use std::path::PathBuf;
 use std::process::Command;
+use std::time::Duration;
+
+/// A single file to be converted, and the decisions the frontend made about it.
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub input_path: PathBuf,
+    pub output_path: PathBuf,
+    pub mappings: StreamMappings,
+    /// What each stream is encoded to, or `None` if it is copied.
+    pub codecs: HashMap<usize, Option<Target>>,
+    /// The duration of the input file, if known.
+    pub duration: Option<Duration>,
+    /// Whether HDR video is tone-mapped to SDR.
+    pub tonemap: bool,
+    /// The crop applied to re-encoded video, either given or detected.
+    pub crop: Option<Crop>,
+}
+
+impl Job {
+    /// Whether every stream is copied, rather than re-encoded.
+    pub fn is_remux(&self) -> bool {
+        self.codecs.values().all(Option::is_none)
+    }
+
+    pub fn has_hdr_video(&self) -> bool {
+        self.mappings.video.iter().filter_map(Stream::as_video).any(Video::is_hdr)
+    }
+}
+
+/// A job, along with how a backend intends to perform it.
+#[derive(Debug, Clone)]
+pub struct PlannedJob {
+    pub job: Job,
+    /// The full ffmpeg command line, for backends that run the ffmpeg binary.
+    pub command: Option<Vec<OsString>>,
+}
+
+/// Backends are shared between the threads that run jobs.
+pub trait Backend: Sync {
+    fn name(&self) -> &'static str;
+
+    /// Decide how a job will be performed, without doing any work.
+    fn plan(&self, job: Job, args: &Options) -> Result<PlannedJob>;
 
-pub fn generate_output_filename<P: AsRef<Path>>(path: P, tv_options: &TVOptions) -> String {
+    /// Perform a planned job, reporting how far through the file it is to `progress`.
+    fn execute(&self, plan: &PlannedJob, args: &Options, progress: &FileProgress) -> Result<()>;
+
+    /// Report the outcome of a job.
+    fn report(&self, plan: &PlannedJob, result: &Result<()>) {
+        match result {
+            Ok(_) => info!("Converted '{}' ({} backend)", plan.job.output_path.display(), self.name()),
+            Err(e) => error!("Failed to convert '{}' ({} backend): {}", plan.job.input_path.display(), self.name(), e),
+        }
+    }
+}
+
+/// Plan, execute, then report on a job.
+pub fn run(backend: &dyn Backend, job: Job, args: &Options, progress: &FileProgress) -> Result<()> {
+    let plan = backend.plan(job, args)?;
+    let result = backend.execute(&plan, args, progress);
+    backend.report(&plan, &result);
+    return result;
+}
+
+pub fn generate_output_filename<P: AsRef<Path>>(path: P, tv_options: &TVOptions) -> Result<String> {
     let path = path.as_ref();
     if tv_options.enabled {
-        return format!(
+        return Ok(format!(
             "{} - s{:02}e{:02}.mkv",
             tv_options.title.as_ref().unwrap(),
             tv_options.season.unwrap(),
             tv_options.episode.unwrap()
-        );
+        ));
     } else {
-        let input_filename = path.file_name().expect("Input filename is None").to_string_lossy();
-        let input_ext = path.extension().expect("Input ext is None").to_string_lossy();
-        let output_filename = input_filename.replace(input_ext.as_ref(), "mkv");
-        return output_filename;
+        let input_filename = path
+            .file_name()
+            .ok_or_else(|| Error::InvalidArguments(format!("'{}' is not a file", path.display())))?;
+        let output_filename = Path::new(input_filename).with_extension("mkv");
+        return Ok(output_filename.to_string_lossy().into_owned());
     }
 }
 
-fn get_encoder(codec: codec::Id) -> Result<&'static str, SimpleError> {
+/// The name of the encoder used to produce `codec`.
+pub fn get_encoder(codec: codec::Id) -> Result<&'static str> {
     use codec::Id;
     match codec {
         Id::AAC => Ok("libfdk_aac"),
         Id::FLAC => Ok("flac"),
         Id::H264 => Ok("libx264"),
         Id::HEVC => Ok("hevc_nvenc"),
-        _ => {
-            error!("Invalid codec '{:?}' passed to get_encoder.", codec);
-            return Err(SimpleError::new("Invalid codec passed to get_encoder"));
-        }
+        Id::AV1 => Ok("libsvtav1"),
+        Id::SSA | Id::ASS => Ok("ass"),
+        _ => Err(Error::UnsupportedStream(format!("No encoder for codec '{:?}'", codec))),
     }
 }
 
-pub fn generate_ffmpeg_command<P: AsRef<Path>>(
-    input_path: P,
-    output_path: P,
-    mappings: &StreamMappings,
-    codecs: &HashMap<usize, Option<codec::Id>>,
-    //tv_options: &TVOptions,
-    args: &Opt,
-) -> Result<std::process::Command, Box<dyn std::error::Error>> {
-    let mut command = Command::new("ffmpeg");
+/// How a video stream is deinterlaced, if it needs deinterlacing. Video whose frames have been analysed is
+/// deinterlaced to suit its content, while anything else is deinterlaced if its field order says it is interlaced.
+fn deinterlace_filter(video_stream: &Video, args: &Options) -> Option<Deinterlace> {
+    if args.no_deinterlace {
+        return None;
+    }
+    return match video_stream.scan {
+        Some(ScanType::Progressive) if args.force_deinterlace => Some(Deinterlace::Yadif),
+        Some(ScanType::Progressive) => None,
+        Some(ScanType::Interlaced) => Some(Deinterlace::Bwdif),
+        Some(ScanType::Telecined) => Some(Deinterlace::InverseTelecine),
+        Some(ScanType::Mixed) => Some(Deinterlace::InterlacedFramesOnly),
+        None if args.force_deinterlace => Some(Deinterlace::Yadif),
+        None => match video_stream.field_order {
+            FieldOrder::Interlaced => Some(Deinterlace::Yadif),
+            _ => None,
+        },
+    };
+}
+
+/// Options for the matroska muxer. The default flags are written as [`output_tags`] sets them, rather than the muxer
+/// picking default subtitles itself.
+pub const MUXER_OPTIONS: &[(&str, &str)] = &[("default_mode", "passthrough")];
+
+/// The metadata written to an output stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputTags {
+    pub language: Option<String>,
+    pub disposition: Disposition,
+}
+
+/// The metadata of each output stream, by the index of its input stream. Languages are tagged in their normalised
+/// form, whatever the input called them, so that players match them consistently. The dispositions are those of
+/// [`StreamMappings::output_dispositions`], which only work if the muxer is given [`MUXER_OPTIONS`].
+pub fn output_tags(mappings: &StreamMappings) -> HashMap<usize, OutputTags> {
+    let dispositions = mappings.output_dispositions();
+    return mappings
+        .iter()
+        .map(|stream| {
+            let tags = OutputTags {
+                language: stream.lang().map(str::to_string),
+                disposition: dispositions[&stream.index()],
+            };
+            (stream.index(), tags)
+        })
+        .collect();
+}
 
-    let video_stream = &match &mappings.video[0] {
-        Stream::Video(x) => Some(x),
-        _ => None,
+/// The filters applied to a re-encoded video stream.
+pub fn video_filters(job: &Job, video_stream: &Video, args: &Options) -> FilterGraph {
+    let mut graph = FilterGraph::new();
+    if let Some(crop) = job.crop {
+        graph.push(Filter::Crop(crop));
     }
-    .unwrap();
+    // Fields are separated before tone-mapping, which would otherwise blend them together
+    if let Some(deinterlace) = deinterlace_filter(video_stream, args) {
+        graph.push(Filter::Deinterlace(deinterlace));
+    }
+    if job.tonemap && video_stream.is_hdr() {
+        graph.push(Filter::Tonemap);
+    }
+    if let Some(denoise) = args.denoise {
+        graph.push(Filter::Denoise(denoise));
+    }
+    if let Some(scale) = video_stream.downscale(job.crop, args) {
+        graph.push(Filter::Scale(scale));
+    }
+    return graph;
+}
 
-    let reencoding_video = codecs.get(&0usize).unwrap().is_some() || args.force_reencode;
-    let reencoding_audio = itertools::any(mappings.audio.iter().map(|x| x.index()), |x| codecs.get(&x).unwrap().is_some());
+/// The options passed to the encoder of a stream. For video, the CRF and tune are added to the options from the codec
+/// policy, converted for the encoder, along with the options that preserve the video's bit depth and colour, or that
+/// describe the SDR video if it is tone-mapped.
+pub fn encoder_options(target: &Target, video: Option<&Video>, tonemap: bool, args: &Options) -> Result<BTreeMap<String, String>> {
+    let mut options = target.options.clone();
+    if let Some(video) = video {
+        let encoder = target.encoder()?;
+        let added = quality_options(encoder, args.crf, args.tune)
+            .into_iter()
+            .chain(color_options(video, encoder, tonemap));
+        for (key, value) in added {
+            // The *-params options are lists of key=value pairs, so are added to rather than replaced
+            match options.get_mut(key) {
+                Some(existing) if key.ends_with("-params") => {
+                    existing.push(':');
+                    existing.push_str(&value);
+                }
+                _ => {
+                    options.insert(key.to_string(), value);
+                }
+            }
+        }
+    }
+    return Ok(options);
+}
 
-    command.arg("-hide_banner");
+/// The options that carry `video`'s bit depth, colour properties and HDR metadata through to `encoder`.
+fn color_options(video: &Video, encoder: &str, tonemap: bool) -> Vec<(&'static str, String)> {
+    if tonemap && video.is_hdr() {
+        let bt709 = ["color_primaries", "color_trc", "colorspace"].iter().map(|x| (*x, "bt709".to_string()));
+        let mut options: Vec<(&'static str, String)> = bt709.chain(std::iter::once(("color_range", "tv".to_string()))).collect();
+        // The tone-mapped video is 8-bit, but nvenc takes its pixel format from the frames uploaded to the GPU
+        if !encoder.ends_with("_nvenc") {
+            options.push(("pix_fmt", "yuv420p".to_string()));
+        }
+        return options;
+    }
 
-    if !args.no_hwaccel {
-        command.arg("-hwaccel");
-        command.arg("auto");
+    let mut options = Vec::new();
+
+    if video.bit_depth.is_some_and(|x| x > 8) {
+        let ten_bit = "yuv420p10le".to_string();
+        match encoder {
+            "libx264" => {
+                options.push(("pix_fmt", ten_bit));
+                options.push(("profile", "high10".to_string()));
+            }
+            "libx265" => {
+                options.push(("pix_fmt", ten_bit));
+                options.push(("profile", "main10".to_string()));
+            }
+            "libsvtav1" | "libaom-av1" => options.push(("pix_fmt", ten_bit)),
+            // nvenc takes its pixel format from the frames uploaded to the GPU
+            "hevc_nvenc" => options.push(("profile", "main10".to_string())),
+            _ => {}
+        }
     }
 
-    command.arg("-i");
-    command.arg(input_path.as_ref().as_os_str());
-    command.args(&["-max_muxing_queue_size", "16384"]);
+    let colors = [
+        ("color_primaries", &video.color_primaries),
+        ("color_trc", &video.color_transfer),
+        ("colorspace", &video.color_space),
+        ("color_range", &video.color_range),
+    ];
+    for (key, value) in colors.iter() {
+        if let Some(value) = value {
+            options.push((*key, value.clone()));
+        }
+    }
 
-    let generate_codec_args = |command: &mut Command, stream_type: char, index_in: usize, index_out: usize| -> Result<(), SimpleError> {
-        command.arg(format!("-c:{}:{}", stream_type, index_out));
-        let codec = codecs.get(&index_in).expect("Codec not found in map");
-        if codec.is_none() {
-            command.arg("copy");
-        } else {
-            command.arg(get_encoder(codec.unwrap())?);
+    if !video.is_hdr() {
+        return options;
+    }
+
+    // x264 and x265 take chromaticity in units of 0.00002, and luminance in units of 0.0001 cd/m²
+    let mastering_display = |x: &MasteringDisplay, format: &dyn Fn(f64, f64) -> String| {
+        let point = |p: (f64, f64)| format!("({},{})", format(p.0, 50000.0), format(p.1, 50000.0));
+        format!(
+            "G{}B{}R{}WP{}L({},{})",
+            point(x.primaries[1]),
+            point(x.primaries[2]),
+            point(x.primaries[0]),
+            point(x.white_point),
+            format(x.max_luminance, 10000.0),
+            format(x.min_luminance, 10000.0)
+        )
+    };
+    let integer = |x: f64, scale: f64| format!("{}", (x * scale).round() as u64);
+    let decimal = |x: f64, _: f64| format!("{:.4}", x);
+    let content_light = video.content_light.map(|x| format!("{},{}", x.max_cll, x.max_fall));
+
+    let (key, params) = match encoder {
+        "libx265" => {
+            let mut params = vec!["hdr10=1".to_string()];
+            params.extend(
+                video
+                    .mastering_display
+                    .as_ref()
+                    .map(|x| format!("master-display={}", mastering_display(x, &integer))),
+            );
+            params.extend(content_light.map(|x| format!("max-cll={}", x)));
+            ("x265-params", params)
         }
-        Ok(())
+        "libx264" => {
+            let mut params = Vec::new();
+            params.extend(
+                video
+                    .mastering_display
+                    .as_ref()
+                    .map(|x| format!("mastering-display={}", mastering_display(x, &integer))),
+            );
+            params.extend(content_light.map(|x| format!("cll={}", x)));
+            ("x264-params", params)
+        }
+        "libsvtav1" => {
+            let mut params = Vec::new();
+            params.extend(
+                video
+                    .mastering_display
+                    .as_ref()
+                    .map(|x| format!("mastering-display={}", mastering_display(x, &decimal))),
+            );
+            params.extend(content_light.map(|x| format!("content-light={}", x)));
+            ("svtav1-params", params)
+        }
+        _ => return options,
     };
+    if !params.is_empty() {
+        options.push((key, params.join(":")));
+    }
+    return options;
+}
 
-    for (out_index, stream) in mappings.video.iter().enumerate() {
-        generate_codec_args(&mut command, 'v', stream.index(), out_index)?;
-    }
-
-    if reencoding_video {
-        trace!("Reencoding video");
-        if args.gpu {
-            command.args(&["-rc", "constqp", "-qp"]);
-            command.arg(args.crf.to_string());
-            command.args(&["-preset", "slow", "-profile:v", "main", "-b:v", "0", "-rc-lookahead", "32"]);
-        } else {
-            command.arg("-crf");
-            command.arg(args.crf.to_string());
-            command.args(&["-profile:v", "high", "-rc-lookahead", "250", "-preset", "slow"]);
-            if !args.no_hwaccel {
-                command.args(&["-x264opts", "opencl"]);
+/// The options that set `encoder`'s quality to the equivalent of a libx264 CRF, and its tune to the nearest
+/// equivalent of a libx264 tune. Tunes with no equivalent are left out.
+fn quality_options(encoder: &str, crf: u8, tune: Option<Libx264Tune>) -> Vec<(&'static str, String)> {
+    let mut options = Vec::new();
+    match encoder {
+        // nvenc has no CRF, the codec policy puts it in constant QP mode instead
+        x if x.ends_with("_nvenc") => options.push(("qp", crf.to_string())),
+        "libx264" => {
+            options.push(("crf", crf.to_string()));
+            if let Some(tune) = tune {
+                options.push(("tune", tune.as_str().to_string()));
             }
-
-            if let Some(x) = args.tune.as_ref() {
-                let s = x.to_string().to_lowercase();
-                command.arg("-tune");
-                command.arg(s);
+        }
+        // x265 needs a CRF about 5 higher than x264 for the same quality
+        "libx265" => {
+            options.push(("crf", crf.saturating_add(5).min(51).to_string()));
+            match tune {
+                Some(Libx264Tune::Film) | Some(Libx264Tune::Stillimage) | None => {}
+                Some(tune) => options.push(("tune", tune.as_str().to_string())),
             }
         }
+        // The AV1 encoders' CRF goes up to 63, and needs to be about 10 higher than x264 for the same quality
+        "libsvtav1" => {
+            options.push(("crf", crf.saturating_add(10).min(63).to_string()));
+            match tune {
+                Some(Libx264Tune::Grain) => options.push(("svtav1-params", "film-grain=8".to_string())),
+                Some(Libx264Tune::Psnr) => options.push(("svtav1-params", "tune=1".to_string())),
+                _ => {}
+            }
+        }
+        "libaom-av1" => {
+            options.push(("crf", crf.saturating_add(10).min(63).to_string()));
+            // Without this, libaom treats the CRF as a cap on a target bitrate
+            options.push(("b", "0".to_string()));
+            match tune {
+                Some(Libx264Tune::Grain) => options.push(("denoise-noise-level", "10".to_string())),
+                Some(Libx264Tune::Psnr) => options.push(("tune", "psnr".to_string())),
+                Some(Libx264Tune::Ssim) => options.push(("tune", "ssim".to_string())),
+                _ => {}
+            }
+        }
+        _ => options.push(("crf", crf.to_string())),
+    }
+    return options;
+}
 
-        let deinterlace = !args.no_deinterlace
-            && (args.force_deinterlace
-                || match video_stream.field_order {
-                    crate::frontend::FieldOrder::Interlaced => true,
-                    _ => false,
-                });
+pub fn generate_ffmpeg_command(job: &Job, args: &Options) -> Result<std::process::Command> {
+    let mut command = Command::new("ffmpeg");
+    let mappings = &job.mappings;
+    let codecs = &job.codecs;
+
+    // Anything more than warnings would garble the progress bars
+    command.args(&["-hide_banner", "-loglevel", "warning"]);
+    // Progress is read from stdout and displayed by us, rather than by ffmpeg
+    command.args(&["-progress", "pipe:1", "-nostats"]);
+
+    if !args.no_hwaccel {
+        command.arg("-hwaccel");
+        command.arg("auto");
+    }
+
+    command.arg("-i");
+    command.arg(job.input_path.as_os_str());
+    command.arg("-max_muxing_queue_size").arg(args.max_muxing_queue_size.to_string());
+    for (key, value) in MUXER_OPTIONS {
+        command.arg(format!("-{}", key)).arg(value);
+    }
 
-        let crop = args.crop.is_some();
+    let tags = output_tags(mappings);
 
-        if deinterlace || crop {
-            command.arg("-filter:v");
+    let generate_codec_args = |command: &mut Command, stream_type: char, stream: &Stream, index_out: usize| -> Result<()> {
+        let tags = &tags[&stream.index()];
+        if let Some(lang) = tags.language.as_ref() {
+            command.arg(format!("-metadata:s:{}:{}", stream_type, index_out));
+            command.arg(format!("language={}", lang));
         }
+        command.arg(format!("-disposition:{}:{}", stream_type, index_out));
+        command.arg(tags.disposition.to_ffmpeg());
+        command.arg(format!("-c:{}:{}", stream_type, index_out));
+        let target = match codecs.get(&stream.index()).expect("Codec not found in map") {
+            Some(x) => x,
+            None => {
+                command.arg("copy");
+                return Ok(());
+            }
+        };
 
-        if crop {
-            let filter = args.crop.as_ref().unwrap();
-            trace!("Cropping video with filter '{}'", filter);
-            command.arg(format!("{}{}", filter, if deinterlace { "," } else { "" }));
+        let encoder = target.encoder()?;
+        command.arg(encoder);
+        for (key, value) in encoder_options(target, stream.as_video(), job.tonemap, args)? {
+            command.arg(format!("-{}:{}:{}", key, stream_type, index_out));
+            command.arg(value);
         }
+        if encoder == "libx264" && !args.no_hwaccel {
+            command.arg(format!("-x264opts:{}:{}", stream_type, index_out));
+            command.arg("opencl");
+        }
+        Ok(())
+    };
 
-        if deinterlace {
-            trace!("Deinterlacing video");
-            if args.gpu {
-                command.args(&["hwupload_cuda", "yadif_cuda"]);
-            } else {
-                command.arg("yadif");
+    for (out_index, stream) in mappings.video.iter().enumerate() {
+        generate_codec_args(&mut command, 'v', stream, out_index)?;
+        // Each re-encoded video stream is filtered to suit its own size and scan type
+        if let (Stream::Video(video), Some(Some(_))) = (stream, codecs.get(&stream.index())) {
+            let graph = video_filters(job, video, args);
+            if !graph.is_empty() {
+                let filter = graph.render(Device::for_options(args));
+                trace!("Filtering video stream {} with '{}'", video.index, filter);
+                command.arg(format!("-filter:v:{}", out_index)).arg(filter);
             }
         }
     }
 
     for (out_index, stream) in mappings.audio.iter().enumerate() {
-        generate_codec_args(&mut command, 'a', stream.index(), out_index)?;
-    }
-
-    if reencoding_audio {
-        trace!("Reencoding audio");
-        // Flac ignores these, its just for libfdk_aac
-        command.args(&["-cutoff", "18000", "-vbr", "5"]);
+        generate_codec_args(&mut command, 'a', stream, out_index)?;
     }
 
     for (out_index, stream) in mappings.subtitle.iter().enumerate() {
-        generate_codec_args(&mut command, 's', stream.index(), out_index)?;
+        generate_codec_args(&mut command, 's', stream, out_index)?;
     }
 
     for stream in mappings.iter() {
@@ -157,7 +454,60 @@ pub fn generate_ffmpeg_command<P: AsRef<Path>>(
         command.arg(format!("0:{}", stream.index()));
     }
 
-    command.arg(output_path.as_ref().as_os_str());
+    command.arg(job.output_path.as_os_str());
 
     return Ok(command);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::Denoise;
+
+    #[test]
+    fn filters_run_in_order() {
+        let video = Video {
+            width: 3840,
+            height: 2160,
+            field_order: FieldOrder::Interlaced,
+            color_transfer: Some("smpte2084".to_string()),
+            ..Video::default()
+        };
+        let crop = Crop {
+            width: 3840,
+            height: 1600,
+            x: 0,
+            y: 280,
+        };
+        let job = Job {
+            input_path: PathBuf::from("input.mkv"),
+            output_path: PathBuf::from("output.mkv"),
+            mappings: StreamMappings {
+                video: vec![Stream::Video(video.clone())],
+                audio: Vec::new(),
+                subtitle: Vec::new(),
+            },
+            codecs: HashMap::new(),
+            duration: None,
+            tonemap: true,
+            crop: Some(crop),
+        };
+        let args = Options {
+            denoise: Some(Denoise::Medium),
+            max_height: Some(1080),
+            ..Options::default()
+        };
+
+        let steps = video_filters(&job, &video, &args).steps(Device::Cpu);
+        let order: Vec<usize> = ["crop=", "yadif", "zscale", "hqdn3d=", "scale="]
+            .iter()
+            .map(|prefix| {
+                steps
+                    .iter()
+                    .position(|x| x.starts_with(prefix))
+                    .unwrap_or_else(|| panic!("No {} in {:?}", prefix, steps))
+            })
+            .collect();
+        assert!(order.windows(2).all(|x| x[0] < x[1]), "{:?}", steps);
+    }
+}
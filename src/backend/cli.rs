@@ -0,0 +1,75 @@
+use super::generate_ffmpeg_command;
+use super::{Backend, Job, PlannedJob};
+use crate::error::{Error, Result};
+use crate::options::Options;
+use crate::progress::{FileProgress, ProgressParser};
+
+use log::{info, warn};
+use std::ffi::{OsStr, OsString};
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+
+/// Performs conversions by running the ffmpeg binary.
+pub struct CliBackend {
+    /// Capture ffmpeg's output and log it once it exits, rather than letting it write to the terminal. This is needed
+    /// when running jobs in parallel, so that their output is not interleaved.
+    pub capture_output: bool,
+}
+
+impl Backend for CliBackend {
+    fn name(&self) -> &'static str {
+        "ffmpeg"
+    }
+
+    fn plan(&self, job: Job, args: &Options) -> Result<PlannedJob> {
+        let command = generate_ffmpeg_command(&job, args)?;
+
+        let command: Vec<OsString> = std::iter::once(command.get_program())
+            .chain(command.get_args())
+            .map(OsStr::to_os_string)
+            .collect();
+
+        info!("{:?}", command);
+
+        Ok(PlannedJob { job, command: Some(command) })
+    }
+
+    fn execute(&self, plan: &PlannedJob, _args: &Options, progress: &FileProgress) -> Result<()> {
+        let argv = plan.command.as_ref().expect("ffmpeg backend was given a job without a command");
+        let mut command = Command::new(&argv[0]);
+        command.args(&argv[1..]).stdout(Stdio::piped());
+        if self.capture_output {
+            command.stderr(Stdio::piped());
+        }
+        let mut child = command.spawn()?;
+
+        let stderr_reader = child
+            .stderr
+            .take()
+            .map(|stderr| std::thread::spawn(move || BufReader::new(stderr).lines().filter_map(Result::ok).collect::<Vec<_>>()));
+
+        let mut parser = ProgressParser::default();
+        for line in BufReader::new(child.stdout.take().unwrap()).lines() {
+            if let Some(update) = parser.parse_line(&line?) {
+                progress.update(&update);
+            }
+        }
+
+        let status = child.wait()?;
+
+        let stderr_lines = stderr_reader.map(|reader| reader.join().unwrap_or_default()).unwrap_or_default();
+        for line in stderr_lines.iter() {
+            warn!("ffmpeg: {}", line);
+        }
+
+        if !status.success() {
+            // ffmpeg's last line of output is usually the reason it failed
+            return Err(Error::Encoder(match stderr_lines.last() {
+                Some(line) => format!("ffmpeg failed ({}): {}", status, line),
+                None => format!("ffmpeg failed ({})", status),
+            }));
+        }
+
+        Ok(())
+    }
+}
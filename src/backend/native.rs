@@ -0,0 +1,518 @@
+use super::encoder_options;
+use super::output_tags;
+use super::video_filters;
+use super::MUXER_OPTIONS;
+use super::{Backend, Job, PlannedJob};
+use crate::error::Error;
+use crate::filtergraph::Device;
+use crate::frontend::{Stream, Video};
+use crate::options::Options;
+use crate::progress::FileProgress;
+use crate::sample::video_buffer_args;
+
+use ffmpeg::codec;
+use ffmpeg::{decoder, encoder, filter, format, frame, Dictionary, Packet, Rational, Rescale};
+use log::{debug, info, trace};
+use std::collections::HashMap;
+use std::os::raw::c_int;
+use std::time::Duration;
+
+/// Size of the buffer that encoded subtitles are written into. This is the same size the ffmpeg cli uses.
+const SUBTITLE_BUFFER_SIZE: usize = 1024 * 1024;
+
+struct OutputStream {
+    index: usize,
+    in_time_base: Rational,
+    out_time_base: Rational,
+    transcoder: Option<Transcoder>,
+}
+
+enum Transcoder {
+    Video(VideoTranscoder),
+    Audio(AudioTranscoder),
+    Subtitle(SubtitleTranscoder),
+}
+
+struct VideoTranscoder {
+    decoder: decoder::Video,
+    filter: filter::Graph,
+    encoder: encoder::video::Encoder,
+    encoder_time_base: Rational,
+}
+
+struct AudioTranscoder {
+    decoder: decoder::Audio,
+    filter: filter::Graph,
+    filter_time_base: Rational,
+    encoder: encoder::audio::Encoder,
+    encoder_time_base: Rational,
+}
+
+struct SubtitleTranscoder {
+    decoder: decoder::Subtitle,
+    encoder: encoder::subtitle::Encoder,
+}
+
+/// Performs conversions in-process using libav*, rather than by running the ffmpeg binary.
+pub struct NativeBackend;
+
+impl Backend for NativeBackend {
+    fn name(&self) -> &'static str {
+        "native"
+    }
+
+    fn plan(&self, job: Job, _args: &Options) -> Result<PlannedJob, Error> {
+        Ok(PlannedJob { job, command: None })
+    }
+
+    fn execute(&self, plan: &PlannedJob, args: &Options, progress: &FileProgress) -> Result<(), Error> {
+        let job = &plan.job;
+        info!("Converting '{}' with the native backend", job.input_path.display());
+        convert(job, args, progress)
+    }
+}
+
+/// The filters for a re-encoded video stream, as libav* expects them. Frames are always filtered on the CPU, as they are
+/// passed to the encoder from system memory.
+fn filter_spec(job: &Job, video: &Video, args: &Options) -> String {
+    let filters = video_filters(job, video, args);
+    if filters.is_empty() {
+        return "null".to_string();
+    }
+    let filter_spec = filters.render(Device::Cpu);
+    trace!("Filtering video stream {} with '{}'", video.index, filter_spec);
+    return filter_spec;
+}
+
+/// Perform the conversion in-process using libav*, rather than by running the ffmpeg binary.
+pub fn convert(job: &Job, args: &Options, progress: &FileProgress) -> Result<(), Error> {
+    let mappings = &job.mappings;
+    let codecs = &job.codecs;
+    let mut ictx = format::input(&job.input_path).map_err(|source| Error::Probe {
+        path: job.input_path.clone(),
+        source,
+    })?;
+    let mut octx = format::output(&job.output_path)?;
+
+    let mut streams: HashMap<usize, OutputStream> = HashMap::new();
+    let tags = output_tags(mappings);
+
+    for (out_index, stream) in mappings.iter().enumerate() {
+        let index = stream.index();
+        let ist = ictx.stream(index).expect("Mapped stream not found in input");
+        let in_time_base = ist.time_base();
+
+        let target = codecs.get(&index).expect("Codec not found in map");
+
+        let transcoder = match target {
+            None => {
+                let mut ost = octx.add_stream(encoder::find(codec::Id::None))?;
+                ost.set_parameters(ist.parameters());
+                ost.set_metadata(ist.metadata().to_owned());
+                // Reset the codec tag, the one from the input container may not be valid in matroska
+                unsafe {
+                    (*ost.parameters().as_mut_ptr()).codec_tag = 0;
+                }
+                None
+            }
+            Some(target) => {
+                let encoder_name = target.encoder()?;
+                debug!("Transcoding stream {} with encoder '{}'", index, encoder_name);
+                let encoder_codec = encoder::find_by_name(encoder_name)
+                    .ok_or_else(|| Error::UnsupportedStream(format!("Encoder '{}' is not available", encoder_name)))?;
+                let mut options = Dictionary::new();
+                for (key, value) in encoder_options(target, stream.as_video(), job.tonemap, args)?.iter() {
+                    options.set(key, value);
+                }
+                Some(match stream {
+                    Stream::Video(video) => {
+                        let filter_spec = filter_spec(job, video, args);
+                        Transcoder::Video(VideoTranscoder::new(&ist, &mut octx, encoder_codec, &filter_spec, options)?)
+                    }
+                    Stream::Audio(_) => Transcoder::Audio(AudioTranscoder::new(&ist, &mut octx, encoder_codec, options)?),
+                    Stream::Subtitle(_) => Transcoder::Subtitle(SubtitleTranscoder::new(&ist, &mut octx, encoder_codec, options)?),
+                })
+            }
+        };
+
+        let mut ost = octx.stream_mut(out_index).unwrap();
+        let tags = &tags[&index];
+        if let Some(lang) = tags.language.as_ref() {
+            let mut metadata = ost.metadata().to_owned();
+            metadata.set("language", lang);
+            ost.set_metadata(metadata);
+        }
+        unsafe {
+            (*ost.as_mut_ptr()).disposition = tags.disposition.to_flags().bits();
+        }
+
+        streams.insert(
+            index,
+            OutputStream {
+                index: out_index,
+                in_time_base,
+                out_time_base: in_time_base,
+                transcoder,
+            },
+        );
+    }
+
+    octx.set_metadata(ictx.metadata().to_owned());
+    let mut muxer_options = Dictionary::new();
+    for (key, value) in MUXER_OPTIONS {
+        muxer_options.set(key, value);
+    }
+    octx.write_header_with(muxer_options)?;
+
+    // The muxer is free to change the time bases of the output streams when writing the header
+    for stream in streams.values_mut() {
+        stream.out_time_base = octx.stream(stream.index).unwrap().time_base();
+    }
+
+    for (ist, mut packet) in ictx.packets() {
+        let stream = match streams.get_mut(&ist.index()) {
+            Some(x) => x,
+            None => continue,
+        };
+
+        if let Some(pts) = packet.pts() {
+            let time = pts.rescale(stream.in_time_base, (1, 1_000_000));
+            progress.set_time(Duration::from_micros(time.max(0) as u64));
+        }
+
+        match stream.transcoder.as_mut() {
+            None => {
+                packet.rescale_ts(stream.in_time_base, stream.out_time_base);
+                packet.set_position(-1);
+                packet.set_stream(stream.index);
+                packet.write_interleaved(&mut octx)?;
+            }
+            Some(Transcoder::Video(x)) => {
+                x.decoder.send_packet(&packet)?;
+                x.receive_frames(&mut octx, stream.index, stream.out_time_base)?;
+            }
+            Some(Transcoder::Audio(x)) => {
+                x.decoder.send_packet(&packet)?;
+                x.receive_frames(&mut octx, stream.index, stream.out_time_base)?;
+            }
+            Some(Transcoder::Subtitle(x)) => {
+                x.transcode(&packet, &mut octx, stream.index, stream.in_time_base, stream.out_time_base)?;
+            }
+        }
+    }
+
+    // Flush decoders, filters and encoders
+    for stream in streams.values_mut() {
+        match stream.transcoder.as_mut() {
+            Some(Transcoder::Video(x)) => {
+                x.decoder.send_eof()?;
+                x.receive_frames(&mut octx, stream.index, stream.out_time_base)?;
+                x.filter.get("in").unwrap().source().flush()?;
+                x.receive_filtered(&mut octx, stream.index, stream.out_time_base)?;
+                x.encoder.send_eof()?;
+                receive_packets(&mut x.encoder, &mut octx, stream.index, x.encoder_time_base, stream.out_time_base)?;
+            }
+            Some(Transcoder::Audio(x)) => {
+                x.decoder.send_eof()?;
+                x.receive_frames(&mut octx, stream.index, stream.out_time_base)?;
+                x.filter.get("in").unwrap().source().flush()?;
+                x.receive_filtered(&mut octx, stream.index, stream.out_time_base)?;
+                x.encoder.send_eof()?;
+                receive_packets(&mut x.encoder, &mut octx, stream.index, x.encoder_time_base, stream.out_time_base)?;
+            }
+            _ => {}
+        }
+    }
+
+    octx.write_trailer()?;
+
+    return Ok(());
+}
+
+/// Write all packets that the encoder has ready to the output.
+fn receive_packets(
+    encoder: &mut encoder::Encoder,
+    octx: &mut format::context::Output,
+    out_index: usize,
+    encoder_time_base: Rational,
+    out_time_base: Rational,
+) -> Result<(), ffmpeg::Error> {
+    let mut encoded = Packet::empty();
+    while encoder.receive_packet(&mut encoded).is_ok() {
+        encoded.set_stream(out_index);
+        encoded.rescale_ts(encoder_time_base, out_time_base);
+        encoded.write_interleaved(octx)?;
+    }
+    return Ok(());
+}
+
+/// Set the time base the decoder should interpret packet timestamps in. The ffmpeg cli does the same.
+fn set_packet_time_base(decoder: &mut codec::decoder::Opened, time_base: Rational) {
+    unsafe {
+        (*decoder.as_mut_ptr()).pkt_timebase = time_base.into();
+    }
+}
+
+impl VideoTranscoder {
+    fn new(
+        ist: &format::stream::Stream,
+        octx: &mut format::context::Output,
+        encoder_codec: codec::Codec,
+        filter_spec: &str,
+        options: Dictionary,
+    ) -> Result<Self, ffmpeg::Error> {
+        let global_header = octx.format().flags().contains(format::Flags::GLOBAL_HEADER);
+        let mut decoder = ist.codec().decoder().video()?;
+        set_packet_time_base(&mut decoder, ist.time_base());
+
+        // Convert to the pixel format the encoder is asked for, if there is one. Otherwise the filter graph picks the
+        // format the encoder supports that is closest to the filtered video's, as it does for the ffmpeg cli.
+        let formats: Vec<String> = match options.get("pix_fmt") {
+            Some(x) => vec![x.to_string()],
+            None => encoder_codec
+                .video()?
+                .formats()
+                .map(|formats| formats.filter_map(format::Pixel::descriptor).map(|x| x.name().to_string()).collect())
+                .unwrap_or_default(),
+        };
+        let filter_spec = if formats.is_empty() {
+            filter_spec.to_string()
+        } else {
+            format!("{},format=pix_fmts={}", filter_spec, formats.join("|"))
+        };
+
+        let mut filter = filter::Graph::new();
+        let buffer_args = video_buffer_args(&decoder, ist.time_base());
+        filter.add(&filter::find("buffer").unwrap(), "in", &buffer_args)?;
+        filter.add(&filter::find("buffersink").unwrap(), "out", "")?;
+        filter.output("in", 0)?.input("out", 0)?.parse(&filter_spec)?;
+        filter.validate()?;
+
+        let (width, height, pixel_format, time_base, aspect_ratio, frame_rate) = unsafe {
+            let sink = filter.get("out").unwrap().as_ptr();
+            (
+                ffmpeg::ffi::av_buffersink_get_w(sink) as u32,
+                ffmpeg::ffi::av_buffersink_get_h(sink) as u32,
+                format::Pixel::from(std::mem::transmute::<c_int, ffmpeg::ffi::AVPixelFormat>(
+                    ffmpeg::ffi::av_buffersink_get_format(sink),
+                )),
+                Rational::from(ffmpeg::ffi::av_buffersink_get_time_base(sink)),
+                Rational::from(ffmpeg::ffi::av_buffersink_get_sample_aspect_ratio(sink)),
+                Rational::from(ffmpeg::ffi::av_buffersink_get_frame_rate(sink)),
+            )
+        };
+
+        let mut ost = octx.add_stream(encoder_codec)?;
+        let mut encoder = ost.codec().encoder().video()?;
+        encoder.set_width(width);
+        encoder.set_height(height);
+        encoder.set_aspect_ratio(aspect_ratio);
+        encoder.set_format(pixel_format);
+        encoder.set_frame_rate(if frame_rate.numerator() == 0 {
+            decoder.frame_rate()
+        } else {
+            Some(frame_rate)
+        });
+        encoder.set_time_base(time_base);
+        if global_header {
+            encoder.set_flags(codec::Flags::GLOBAL_HEADER);
+        }
+
+        let encoder = encoder.open_as_with(encoder_codec, options)?;
+        ost.set_parameters(&encoder);
+        ost.set_metadata(ist.metadata().to_owned());
+
+        Ok(VideoTranscoder {
+            decoder,
+            filter,
+            encoder,
+            encoder_time_base: time_base,
+        })
+    }
+
+    fn receive_frames(&mut self, octx: &mut format::context::Output, out_index: usize, out_time_base: Rational) -> Result<(), ffmpeg::Error> {
+        let mut decoded = frame::Video::empty();
+        while self.decoder.receive_frame(&mut decoded).is_ok() {
+            let timestamp = decoded.timestamp();
+            decoded.set_pts(timestamp);
+            self.filter.get("in").unwrap().source().add(&decoded)?;
+            self.receive_filtered(octx, out_index, out_time_base)?;
+        }
+        return Ok(());
+    }
+
+    fn receive_filtered(&mut self, octx: &mut format::context::Output, out_index: usize, out_time_base: Rational) -> Result<(), ffmpeg::Error> {
+        let mut filtered = frame::Video::empty();
+        while self.filter.get("out").unwrap().sink().frame(&mut filtered).is_ok() {
+            filtered.set_kind(ffmpeg::picture::Type::None);
+            self.encoder.send_frame(&filtered)?;
+            receive_packets(&mut self.encoder, octx, out_index, self.encoder_time_base, out_time_base)?;
+        }
+        return Ok(());
+    }
+}
+
+impl AudioTranscoder {
+    fn new(
+        ist: &format::stream::Stream,
+        octx: &mut format::context::Output,
+        encoder_codec: codec::Codec,
+        options: Dictionary,
+    ) -> Result<Self, ffmpeg::Error> {
+        let global_header = octx.format().flags().contains(format::Flags::GLOBAL_HEADER);
+        let mut decoder = ist.codec().decoder().audio()?;
+        set_packet_time_base(&mut decoder, ist.time_base());
+        if decoder.channel_layout().is_empty() {
+            let layout = ffmpeg::ChannelLayout::default(decoder.channels() as i32);
+            decoder.set_channel_layout(layout);
+        }
+
+        let audio_codec = encoder_codec.audio()?;
+        let channel_layout = audio_codec
+            .channel_layouts()
+            .map(|layouts| layouts.best(decoder.channel_layout().channels()))
+            .unwrap_or_else(|| decoder.channel_layout());
+        let sample_format = audio_codec.formats().and_then(|mut x| x.next()).unwrap_or_else(|| decoder.format());
+
+        let mut ost = octx.add_stream(encoder_codec)?;
+        let mut encoder = ost.codec().encoder().audio()?;
+        encoder.set_rate(decoder.rate() as i32);
+        encoder.set_channel_layout(channel_layout);
+        encoder.set_channels(channel_layout.channels());
+        encoder.set_format(sample_format);
+        let encoder_time_base = Rational::new(1, decoder.rate() as i32);
+        encoder.set_time_base(encoder_time_base);
+        if global_header {
+            encoder.set_flags(codec::Flags::GLOBAL_HEADER);
+        }
+
+        let encoder = encoder.open_as_with(encoder_codec, options)?;
+        ost.set_parameters(&encoder);
+        ost.set_metadata(ist.metadata().to_owned());
+
+        let mut filter = filter::Graph::new();
+        let buffer_args = format!(
+            "time_base={}:sample_rate={}:sample_fmt={}:channel_layout=0x{:x}",
+            ist.time_base(),
+            decoder.rate(),
+            decoder.format().name(),
+            decoder.channel_layout().bits()
+        );
+        filter.add(&filter::find("abuffer").unwrap(), "in", &buffer_args)?;
+        filter.add(&filter::find("abuffersink").unwrap(), "out", "")?;
+        {
+            let mut out = filter.get("out").unwrap();
+            out.set_sample_format(encoder.format());
+            out.set_channel_layout(encoder.channel_layout());
+            out.set_sample_rate(encoder.rate());
+        }
+        filter.output("in", 0)?.input("out", 0)?.parse("anull")?;
+        filter.validate()?;
+
+        if !encoder_codec
+            .capabilities()
+            .contains(codec::capabilities::Capabilities::VARIABLE_FRAME_SIZE)
+        {
+            filter.get("out").unwrap().sink().set_frame_size(encoder.frame_size());
+        }
+
+        let filter_time_base = unsafe { Rational::from(ffmpeg::ffi::av_buffersink_get_time_base(filter.get("out").unwrap().as_ptr())) };
+
+        Ok(AudioTranscoder {
+            decoder,
+            filter,
+            filter_time_base,
+            encoder,
+            encoder_time_base,
+        })
+    }
+
+    fn receive_frames(&mut self, octx: &mut format::context::Output, out_index: usize, out_time_base: Rational) -> Result<(), ffmpeg::Error> {
+        let mut decoded = frame::Audio::empty();
+        while self.decoder.receive_frame(&mut decoded).is_ok() {
+            let timestamp = decoded.timestamp();
+            decoded.set_pts(timestamp);
+            self.filter.get("in").unwrap().source().add(&decoded)?;
+            self.receive_filtered(octx, out_index, out_time_base)?;
+        }
+        return Ok(());
+    }
+
+    fn receive_filtered(&mut self, octx: &mut format::context::Output, out_index: usize, out_time_base: Rational) -> Result<(), ffmpeg::Error> {
+        let mut filtered = frame::Audio::empty();
+        while self.filter.get("out").unwrap().sink().frame(&mut filtered).is_ok() {
+            let pts = filtered.pts().map(|x| x.rescale(self.filter_time_base, self.encoder_time_base));
+            filtered.set_pts(pts);
+            self.encoder.send_frame(&filtered)?;
+            receive_packets(&mut self.encoder, octx, out_index, self.encoder_time_base, out_time_base)?;
+        }
+        return Ok(());
+    }
+}
+
+impl SubtitleTranscoder {
+    fn new(
+        ist: &format::stream::Stream,
+        octx: &mut format::context::Output,
+        encoder_codec: codec::Codec,
+        options: Dictionary,
+    ) -> Result<Self, ffmpeg::Error> {
+        let mut decoder = ist.codec().decoder().subtitle()?;
+        set_packet_time_base(&mut decoder, ist.time_base());
+
+        let mut ost = octx.add_stream(encoder_codec)?;
+        let mut encoder = ost.codec().encoder().subtitle()?;
+        encoder.set_time_base(ffmpeg::rescale::TIME_BASE);
+
+        // Text subtitle encoders need the header (styles etc.) produced by the decoder
+        unsafe {
+            let decoder_ptr = decoder.as_ptr();
+            let encoder_ptr = encoder.as_mut_ptr();
+            let size = (*decoder_ptr).subtitle_header_size;
+            if !(*decoder_ptr).subtitle_header.is_null() && size > 0 {
+                let header = ffmpeg::ffi::av_mallocz(size as usize + 1) as *mut u8;
+                std::ptr::copy_nonoverlapping((*decoder_ptr).subtitle_header, header, size as usize);
+                (*encoder_ptr).subtitle_header = header;
+                (*encoder_ptr).subtitle_header_size = size;
+            }
+        }
+
+        let encoder = encoder.open_as_with(encoder_codec, options)?;
+        ost.set_parameters(&encoder);
+        ost.set_metadata(ist.metadata().to_owned());
+
+        Ok(SubtitleTranscoder { decoder, encoder })
+    }
+
+    fn transcode(
+        &mut self,
+        packet: &Packet,
+        octx: &mut format::context::Output,
+        out_index: usize,
+        in_time_base: Rational,
+        out_time_base: Rational,
+    ) -> Result<(), ffmpeg::Error> {
+        let mut subtitle = ffmpeg::Subtitle::new();
+        if !self.decoder.decode(packet, &mut subtitle)? {
+            return Ok(());
+        }
+
+        let mut buffer = vec![0u8; SUBTITLE_BUFFER_SIZE];
+        // The safe wrapper does not expose the size of the encoded subtitle
+        let size =
+            unsafe { ffmpeg::ffi::avcodec_encode_subtitle(self.encoder.as_mut_ptr(), buffer.as_mut_ptr(), buffer.len() as c_int, subtitle.as_ptr()) };
+        if size < 0 {
+            return Err(ffmpeg::Error::from(size));
+        }
+
+        let mut encoded = Packet::copy(&buffer[..size as usize]);
+        encoded.set_pts(packet.pts());
+        encoded.set_dts(packet.pts());
+        // Malformed subtitles can end before they start
+        encoded.set_duration(i64::from(subtitle.end().saturating_sub(subtitle.start())).rescale((1, 1000), in_time_base));
+        encoded.rescale_ts(in_time_base, out_time_base);
+        encoded.set_stream(out_index);
+        encoded.write_interleaved(octx)?;
+        return Ok(());
+    }
+}
@@ -0,0 +1,48 @@
+use super::{Backend, Job, PlannedJob};
+use crate::error::Result;
+use crate::options::Options;
+use crate::progress::FileProgress;
+
+use log::info;
+use std::sync::Mutex;
+
+/// Plans jobs with another backend, but records them instead of executing them.
+///
+/// This is used for `--simulate`, so that a simulated run makes exactly the same decisions as a real one.
+pub struct RecordingBackend<'a> {
+    inner: &'a dyn Backend,
+    jobs: Mutex<Vec<PlannedJob>>,
+}
+
+impl<'a> RecordingBackend<'a> {
+    pub fn new(inner: &'a dyn Backend) -> Self {
+        RecordingBackend {
+            inner,
+            jobs: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The jobs that have been planned so far, in the order they were executed.
+    pub fn jobs(&self) -> Vec<PlannedJob> {
+        self.jobs.lock().unwrap().clone()
+    }
+}
+
+impl<'a> Backend for RecordingBackend<'a> {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn plan(&self, job: Job, args: &Options) -> Result<PlannedJob> {
+        self.inner.plan(job, args)
+    }
+
+    fn execute(&self, plan: &PlannedJob, _args: &Options, _progress: &FileProgress) -> Result<()> {
+        self.jobs.lock().unwrap().push(plan.clone());
+        Ok(())
+    }
+
+    fn report(&self, plan: &PlannedJob, _result: &Result<()>) {
+        info!("Simulate mode: not converting '{}'", plan.job.input_path.display());
+    }
+}
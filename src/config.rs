@@ -0,0 +1,517 @@
+use crate::interface::Opt;
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use videoconverter::options::{Denoise, Libx264Tune, Options, Scaler, Tonemap, VideoCodec};
+use videoconverter::policy::CodecPolicy;
+use videoconverter::preset::{Preset, Presets};
+use videoconverter::scan;
+use videoconverter::Error;
+
+/// The name of the configuration file read from the target directory.
+const DIRECTORY_CONFIG: &str = ".videoconverter.toml";
+
+/// One layer of configuration: the global configuration file, the target directory's configuration file, or the
+/// command line. Unset fields are taken from the layer below.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio_langs: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub_langs: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub all_streams: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crf: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tune: Option<Libx264Tune>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crop: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auto_crop: Option<bool>,
+    /// `true` always deinterlaces, `false` never does.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deinterlace: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detect_interlace: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub denoise: Option<Denoise>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scaler: Option<Scaler>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub force_reencode: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gpu: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub video_codec: Option<VideoCodec>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tonemap: Option<Tonemap>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_hwaccel: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_muxing_queue_size: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub native: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jobs: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remux_jobs: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encode_jobs: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recursive: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fail_fast: Option<bool>,
+    /// A codec policy file. Relative paths are relative to the configuration file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub codec_policy: Option<PathBuf>,
+    /// A codec policy, written in place.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy: Option<CodecPolicy>,
+    /// Presets in addition to the built-in ones, replacing any with the same name.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub presets: BTreeMap<String, Preset>,
+}
+
+/// Settings that affect the batch as a whole, rather than how each file is converted.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub native: bool,
+    pub jobs: usize,
+    /// Defaults to `jobs`.
+    pub remux_jobs: Option<usize>,
+    pub encode_jobs: usize,
+    pub recursive: bool,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub fail_fast: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            native: false,
+            jobs: 1,
+            remux_jobs: None,
+            encode_jobs: 1,
+            recursive: false,
+            include: Vec::new(),
+            exclude: scan::DEFAULT_EXCLUDES.iter().map(|x| x.to_string()).collect(),
+            fail_fast: false,
+        }
+    }
+}
+
+/// The configuration in effect, after every layer has been applied.
+pub struct Resolved {
+    pub options: Options,
+    pub settings: Settings,
+    pub presets: Presets,
+    pub preset: Option<String>,
+}
+
+impl Resolved {
+    /// The configuration in effect, as a single layer.
+    pub fn to_config(&self) -> Config {
+        let options = &self.options;
+        let settings = &self.settings;
+        Config {
+            audio_langs: Some(options.audio_langs.clone()),
+            sub_langs: Some(options.sub_langs.clone()),
+            all_streams: Some(options.all_streams),
+            preset: self.preset.clone(),
+            crf: Some(options.crf),
+            tune: options.tune,
+            crop: options.crop.clone(),
+            auto_crop: Some(options.auto_crop),
+            deinterlace: if options.force_deinterlace {
+                Some(true)
+            } else if options.no_deinterlace {
+                Some(false)
+            } else {
+                None
+            },
+            detect_interlace: Some(options.detect_interlace),
+            denoise: options.denoise,
+            max_width: options.max_width,
+            max_height: options.max_height,
+            scaler: Some(options.scaler),
+            force_reencode: Some(options.force_reencode),
+            gpu: Some(options.gpu),
+            video_codec: options.video_codec,
+            tonemap: options.tonemap,
+            no_hwaccel: Some(options.no_hwaccel),
+            max_muxing_queue_size: Some(options.max_muxing_queue_size),
+            native: Some(settings.native),
+            jobs: Some(settings.jobs),
+            remux_jobs: settings.remux_jobs,
+            encode_jobs: Some(settings.encode_jobs),
+            recursive: Some(settings.recursive),
+            include: Some(settings.include.clone()),
+            exclude: Some(settings.exclude.clone()),
+            fail_fast: Some(settings.fail_fast),
+            codec_policy: None,
+            policy: Some(options.policy.clone()),
+            presets: self.presets.0.clone(),
+        }
+    }
+}
+
+/// The path of the global configuration file, `$XDG_CONFIG_HOME/videoconverter/config.toml`.
+fn global_config_path() -> Option<PathBuf> {
+    let config_home = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(x) if !x.is_empty() => PathBuf::from(x),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    return Some(config_home.join("videoconverter").join("config.toml"));
+}
+
+impl Config {
+    /// Read a configuration file, or `None` if it does not exist.
+    pub fn load(path: &Path) -> Result<Option<Config>, Error> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(x) => x,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let mut config: Config =
+            toml::from_str(&contents).map_err(|e| Error::InvalidArguments(format!("Invalid configuration '{}': {}", path.display(), e)))?;
+        if let (Some(policy), Some(dir)) = (config.codec_policy.as_mut(), path.parent()) {
+            *policy = dir.join(&policy);
+        }
+        return Ok(Some(config));
+    }
+
+    /// The layer set by the command line.
+    pub fn from_args(args: &Opt) -> Config {
+        let flag = |x: bool| if x { Some(true) } else { None };
+        let deinterlace = if args.force_deinterlace {
+            Some(true)
+        } else if args.no_deinterlace {
+            Some(false)
+        } else {
+            None
+        };
+        let list = |x: &Vec<String>| if x.is_empty() { None } else { Some(x.clone()) };
+
+        Config {
+            audio_langs: list(&args.audio_langs),
+            sub_langs: list(&args.sub_langs),
+            all_streams: flag(args.all_streams),
+            preset: args.preset.clone(),
+            crf: args.crf,
+            tune: args.tune,
+            crop: args.crop.clone(),
+            auto_crop: flag(args.auto_crop),
+            deinterlace,
+            detect_interlace: flag(args.detect_interlace),
+            denoise: args.denoise,
+            max_width: args.max_width,
+            max_height: args.max_height,
+            scaler: args.scaler,
+            force_reencode: flag(args.force_reencode),
+            gpu: flag(args.gpu),
+            video_codec: args.video_codec,
+            tonemap: args.tonemap,
+            no_hwaccel: flag(args.no_hwaccel),
+            native: flag(args.native),
+            jobs: args.jobs,
+            remux_jobs: args.remux_jobs,
+            encode_jobs: args.encode_jobs,
+            recursive: flag(args.recursive),
+            include: list(&args.include),
+            exclude: list(&args.exclude),
+            fail_fast: flag(args.fail_fast),
+            codec_policy: args.codec_policy.clone(),
+            ..Config::default()
+        }
+    }
+
+    /// Whether this layer chooses to encode on the GPU, without setting the options that contradict it. Encoding on the
+    /// GPU is an alternative to both a software encoder and decoding without hardware acceleration, so choosing one
+    /// replaces the other from the layers below.
+    fn chooses_gpu(&self) -> bool {
+        self.gpu == Some(true) && self.video_codec.is_none() && self.no_hwaccel.is_none()
+    }
+
+    fn chooses_video_codec(&self) -> bool {
+        self.video_codec.is_some() && self.gpu.is_none()
+    }
+
+    fn chooses_no_hwaccel(&self) -> bool {
+        self.no_hwaccel == Some(true) && self.gpu.is_none()
+    }
+
+    /// Overwrite the values in this layer with those set in `other`.
+    pub fn merge(&mut self, other: Config) {
+        if other.chooses_gpu() {
+            self.video_codec = None;
+            self.no_hwaccel = None;
+        }
+        if other.chooses_video_codec() || other.chooses_no_hwaccel() {
+            self.gpu = None;
+        }
+        macro_rules! merge {
+            ($($field:ident),*) => {
+                $(
+                    if other.$field.is_some() {
+                        self.$field = other.$field;
+                    }
+                )*
+            };
+        }
+        merge!(
+            audio_langs,
+            sub_langs,
+            all_streams,
+            preset,
+            crf,
+            tune,
+            crop,
+            auto_crop,
+            deinterlace,
+            detect_interlace,
+            denoise,
+            max_width,
+            max_height,
+            scaler,
+            force_reencode,
+            gpu,
+            video_codec,
+            tonemap,
+            no_hwaccel,
+            max_muxing_queue_size,
+            native,
+            jobs,
+            remux_jobs,
+            encode_jobs,
+            recursive,
+            include,
+            exclude,
+            fail_fast
+        );
+
+        // A policy replaces the one below it, whether it is written in place or in a file
+        if other.codec_policy.is_some() || other.policy.is_some() {
+            self.codec_policy = other.codec_policy;
+            self.policy = other.policy;
+        }
+        self.presets.extend(other.presets);
+    }
+
+    /// Overwrite the options set in this layer, apart from the preset.
+    fn apply(&self, options: &mut Options) -> Result<(), Error> {
+        if let Some(x) = self.audio_langs.as_ref() {
+            options.audio_langs = x.clone();
+        }
+        if let Some(x) = self.sub_langs.as_ref() {
+            options.sub_langs = x.clone();
+        }
+        if let Some(x) = self.all_streams {
+            options.all_streams = x;
+        }
+        if let Some(x) = self.crf {
+            options.crf = x;
+        }
+        if self.tune.is_some() {
+            options.tune = self.tune;
+        }
+        // A crop given in one layer replaces automatic cropping in the layer below, and the other way around
+        if let Some(x) = self.crop.as_ref() {
+            options.crop = Some(x.clone());
+            options.auto_crop = false;
+        }
+        if let Some(x) = self.auto_crop {
+            options.auto_crop = x;
+            if x {
+                options.crop = None;
+            }
+        }
+        if let Some(x) = self.deinterlace {
+            options.force_deinterlace = x;
+            options.no_deinterlace = !x;
+        }
+        if let Some(x) = self.detect_interlace {
+            options.detect_interlace = x;
+        }
+        if self.denoise.is_some() {
+            options.denoise = self.denoise;
+        }
+        if self.max_width.is_some() {
+            options.max_width = self.max_width;
+        }
+        if self.max_height.is_some() {
+            options.max_height = self.max_height;
+        }
+        if let Some(x) = self.scaler {
+            options.scaler = x;
+        }
+        if let Some(x) = self.force_reencode {
+            options.force_reencode = x;
+        }
+        if let Some(x) = self.gpu {
+            options.gpu = x;
+        }
+        if self.video_codec.is_some() {
+            options.video_codec = self.video_codec;
+        }
+        if let Some(x) = self.no_hwaccel {
+            options.no_hwaccel = x;
+        }
+        if self.chooses_gpu() {
+            options.video_codec = None;
+            options.no_hwaccel = false;
+        }
+        if self.chooses_video_codec() || self.chooses_no_hwaccel() {
+            options.gpu = false;
+        }
+        if self.tonemap.is_some() {
+            options.tonemap = self.tonemap;
+        }
+        if let Some(x) = self.max_muxing_queue_size {
+            options.max_muxing_queue_size = x;
+        }
+        if let Some(path) = self.codec_policy.as_ref() {
+            options.policy = CodecPolicy::load(path)?;
+        } else if let Some(policy) = self.policy.as_ref() {
+            options.policy = policy.clone();
+        }
+        return Ok(());
+    }
+
+    fn apply_settings(&self, settings: &mut Settings) {
+        if let Some(x) = self.native {
+            settings.native = x;
+        }
+        if let Some(x) = self.jobs {
+            settings.jobs = x;
+        }
+        if self.remux_jobs.is_some() {
+            settings.remux_jobs = self.remux_jobs;
+        }
+        if let Some(x) = self.encode_jobs {
+            settings.encode_jobs = x;
+        }
+        if let Some(x) = self.recursive {
+            settings.recursive = x;
+        }
+        if let Some(x) = self.include.as_ref() {
+            settings.include = x.clone();
+        }
+        if let Some(x) = self.exclude.as_ref() {
+            settings.exclude = x.clone();
+        }
+        if let Some(x) = self.fail_fast {
+            settings.fail_fast = x;
+        }
+    }
+}
+
+/// Read the global configuration file, then the configuration file in `dir`, then the command line, each overriding
+/// the last. A preset overrides the configuration files, but not the command line.
+pub fn resolve(args: &Opt, dir: &Path) -> Result<Resolved, Error> {
+    let mut files = Config::default();
+    let paths = global_config_path().into_iter().chain(std::iter::once(dir.join(DIRECTORY_CONFIG)));
+    for path in paths {
+        if let Some(config) = Config::load(&path)? {
+            debug!("Read configuration from '{}'", path.display());
+            files.merge(config);
+        }
+    }
+    let preset_file = args.preset_file.as_ref();
+    let mut args = Config::from_args(args);
+
+    let mut presets = Presets::default();
+    presets.extend(files.presets.clone())?;
+    if let Some(path) = preset_file {
+        presets.load(path)?;
+    }
+
+    let mut options = Options::default();
+    files.apply(&mut options)?;
+    let preset = args.preset.clone().or_else(|| files.preset.clone());
+    if let Some(name) = preset.as_ref() {
+        presets.get(name)?.apply(&mut options);
+    }
+    args.apply(&mut options)?;
+    if options.gpu && args.tune.is_none() {
+        // A tune from a configuration file or preset doesn't apply to nvenc
+        options.tune = None;
+    }
+
+    let mut settings = Settings::default();
+    files.apply_settings(&mut settings);
+    // Excludes from the command line add to those configured, rather than replacing them
+    if let Some(exclude) = args.exclude.take() {
+        settings.exclude.extend(exclude);
+    }
+    args.apply_settings(&mut settings);
+
+    return Ok(Resolved {
+        options,
+        settings,
+        presets,
+        preset,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The options from `lower`, with `upper` merged over it and then applied on top, as the command line is.
+    fn layer(lower: Config, upper: Config) -> Options {
+        let mut files = Config::default();
+        files.merge(lower);
+        files.merge(upper.clone());
+        let mut options = Options::default();
+        files.apply(&mut options).unwrap();
+        upper.apply(&mut options).unwrap();
+        return options;
+    }
+
+    #[test]
+    fn gpu_replaces_the_options_it_contradicts() {
+        let gpu = Config {
+            gpu: Some(true),
+            ..Config::default()
+        };
+        let no_hwaccel = Config {
+            no_hwaccel: Some(true),
+            ..Config::default()
+        };
+        let video_codec = Config {
+            video_codec: Some(VideoCodec::Libx265),
+            ..Config::default()
+        };
+
+        let options = layer(no_hwaccel.clone(), gpu.clone());
+        assert!(options.gpu && !options.no_hwaccel);
+        options.validate().unwrap();
+
+        let options = layer(gpu.clone(), no_hwaccel);
+        assert!(!options.gpu && options.no_hwaccel);
+        options.validate().unwrap();
+
+        let options = layer(video_codec.clone(), gpu.clone());
+        assert!(options.gpu && options.video_codec.is_none());
+        options.validate().unwrap();
+
+        let options = layer(gpu, video_codec);
+        assert!(!options.gpu && options.video_codec == Some(VideoCodec::Libx265));
+        options.validate().unwrap();
+    }
+}
@@ -0,0 +1,185 @@
+use crate::error::Error;
+use crate::sample::sample_frames;
+
+use ffmpeg::frame;
+use std::path::Path;
+use std::str::FromStr;
+
+/// The number of frames decoded at each point in the file.
+const FRAMES_PER_POINT: usize = 5;
+/// Rows and columns darker than the limit are border. The limit is a fraction (24/255) so that cropdetect scales it to
+/// the bit depth, as black is 16 in 8-bit video but 64 in 10-bit video. Each frame is measured on its own, rather than
+/// against the largest area seen so far.
+const CROPDETECT_FILTER: &str = "cropdetect=limit=0.094:round=2:reset=1";
+
+/// A crop, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crop {
+    pub width: u32,
+    pub height: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl Crop {
+    /// The crop filter that performs this crop.
+    pub fn filter(&self) -> String {
+        format!("crop={}:{}:{}:{}", self.width, self.height, self.x, self.y)
+    }
+}
+
+/// Parses a crop filter of the format 'crop=width:height:x:y', or the same without the 'crop='.
+impl FromStr for Crop {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidArguments(format!("Invalid crop '{}', expected 'crop=width:height:x:y'", s));
+        let values = s
+            .strip_prefix("crop=")
+            .unwrap_or(s)
+            .split(':')
+            .map(|x| x.trim().parse::<u32>().map_err(|_| invalid()))
+            .collect::<Result<Vec<u32>, Error>>()?;
+        match values[..] {
+            [width, height, x, y] if width > 0 && height > 0 => Ok(Crop { width, height, x, y }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// The area of a frame that is not black, as `(x1, y1, x2, y2)` inclusive, from the metadata cropdetect attaches to
+/// it. Frames that are entirely black have no such area.
+fn content_bounds(frame: &frame::Video) -> Option<(u32, u32, u32, u32)> {
+    let metadata = frame.metadata();
+    let get = |key: &str| metadata.get(&format!("lavfi.cropdetect.{}", key))?.parse::<i64>().ok();
+    let (x1, y1, x2, y2) = (get("x1")?, get("y1")?, get("x2")?, get("y2")?);
+    if x2 < x1 || y2 < y1 || x1 < 0 || y1 < 0 {
+        return None;
+    }
+    return Some((x1 as u32, y1 as u32, x2 as u32, y2 as u32));
+}
+
+/// The offset and size of a crop along one axis that keeps `start..=end`, with both rounded to even numbers so that
+/// chroma subsampling lines up.
+fn even_span(start: u32, end: u32, size: u32) -> (u32, u32) {
+    let offset = start & !1;
+    let length = (end + 1 - offset + 1) & !1;
+    return (offset, length.min(size - offset));
+}
+
+/// Find the black borders of a video stream, by running libav*'s cropdetect filter on frames sampled from across the
+/// file. The crop keeps everything that is not black in any of the sampled frames, so that no content is cut, and is
+/// `None` if there are no borders.
+pub fn detect(path: &Path, stream_index: usize) -> Result<Option<Crop>, Error> {
+    let mut bounds: Option<(u32, u32, u32, u32)> = None;
+    let (width, height) = sample_frames(path, stream_index, CROPDETECT_FILTER, FRAMES_PER_POINT, |frame| {
+        if let Some((x1, y1, x2, y2)) = content_bounds(frame) {
+            bounds = Some(match bounds {
+                Some(b) => (b.0.min(x1), b.1.min(y1), b.2.max(x2), b.3.max(y2)),
+                None => (x1, y1, x2, y2),
+            });
+        }
+    })?;
+
+    let (x1, y1, x2, y2) = match bounds {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let (x, crop_width) = even_span(x1, x2.min(width - 1), width);
+    let (y, crop_height) = even_span(y1, y2.min(height - 1), height);
+    if crop_width == width && crop_height == height {
+        return Ok(None);
+    }
+
+    return Ok(Some(Crop {
+        width: crop_width,
+        height: crop_height,
+        x,
+        y,
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ffmpeg::{filter, format};
+
+    /// The content bounds cropdetect finds in frames that are black apart from a grey band across the middle, from
+    /// row `top` to row `bottom`, in the limited range for `bit_depth`.
+    fn detect_band(pixel: format::Pixel, bit_depth: u32, top: u32, bottom: u32) -> Option<(u32, u32, u32, u32)> {
+        ffmpeg::init().unwrap();
+        let (width, height) = (320, 240);
+        let pixel_format: ffmpeg::ffi::AVPixelFormat = pixel.into();
+        let buffer_args = format!(
+            "video_size={}x{}:pix_fmt={}:time_base=1/25:pixel_aspect=1/1",
+            width, height, pixel_format as std::os::raw::c_int
+        );
+        let mut graph = filter::Graph::new();
+        graph.add(&filter::find("buffer").unwrap(), "in", &buffer_args).unwrap();
+        graph.add(&filter::find("buffersink").unwrap(), "out", "").unwrap();
+        graph.output("in", 0).unwrap().input("out", 0).unwrap().parse(CROPDETECT_FILTER).unwrap();
+        graph.validate().unwrap();
+
+        let shift = bit_depth - 8;
+        let (black, grey, neutral) = (16 << shift, 128 << shift, 128 << shift);
+        let mut frame = frame::Video::new(pixel, width, height);
+        for plane in 0..3 {
+            let stride = frame.stride(plane);
+            let (plane_width, plane_height) = if plane == 0 { (width, height) } else { (width / 2, height / 2) };
+            let data = frame.data_mut(plane);
+            for y in 0..plane_height {
+                for x in 0..plane_width as usize {
+                    let value: u16 = match plane {
+                        0 if y >= top && y <= bottom => grey,
+                        0 => black,
+                        _ => neutral,
+                    };
+                    let row = &mut data[y as usize * stride..];
+                    if bit_depth > 8 {
+                        row[x * 2..x * 2 + 2].copy_from_slice(&value.to_le_bytes());
+                    } else {
+                        row[x] = value as u8;
+                    }
+                }
+            }
+        }
+
+        // cropdetect skips the first couple of frames
+        let mut bounds = None;
+        for pts in 0..5 {
+            frame.set_pts(Some(pts));
+            graph.get("in").unwrap().source().add(&frame).unwrap();
+            let mut filtered = frame::Video::empty();
+            while graph.get("out").unwrap().sink().frame(&mut filtered).is_ok() {
+                bounds = content_bounds(&filtered).or(bounds);
+            }
+        }
+        return bounds;
+    }
+
+    #[test]
+    fn detects_borders_of_8_bit_video() {
+        let (_, y1, _, y2) = detect_band(format::Pixel::YUV420P, 8, 40, 199).expect("No bounds detected");
+        assert_eq!((y1, y2), (40, 199));
+    }
+
+    #[test]
+    fn detects_borders_of_10_bit_video() {
+        let (_, y1, _, y2) = detect_band(format::Pixel::YUV420P10LE, 10, 40, 199).expect("No bounds detected");
+        assert_eq!((y1, y2), (40, 199));
+    }
+
+    #[test]
+    fn parses_crops() {
+        let crop = Crop {
+            width: 1920,
+            height: 800,
+            x: 0,
+            y: 140,
+        };
+        assert_eq!("crop=1920:800:0:140".parse::<Crop>().unwrap(), crop);
+        assert_eq!("1920:800:0:140".parse::<Crop>().unwrap(), crop);
+        assert!("crop=1920:800".parse::<Crop>().is_err());
+        assert!("crop=0:800:0:0".parse::<Crop>().is_err());
+    }
+}
@@ -0,0 +1,62 @@
+use std::fmt;
+use std::path::PathBuf;
+
+/// Everything that can go wrong while converting files.
+#[derive(Debug)]
+pub enum Error {
+    /// A file could not be opened or read by libav*.
+    Probe {
+        path: PathBuf,
+        source: ffmpeg::Error,
+    },
+    /// A file contains a stream that can't be converted.
+    UnsupportedStream(String),
+    /// The options given were contradictory or malformed.
+    InvalidArguments(String),
+    Io(std::io::Error),
+    /// ffmpeg or libav* failed partway through a conversion.
+    Encoder(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Probe { path, source } => write!(f, "Failed to probe '{}': {}", path.display(), source),
+            Error::UnsupportedStream(x) => write!(f, "Unsupported stream: {}", x),
+            Error::InvalidArguments(x) => write!(f, "Invalid arguments: {}", x),
+            Error::Io(x) => write!(f, "I/O error: {}", x),
+            Error::Encoder(x) => write!(f, "Encoding failed: {}", x),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Probe { source, .. } => Some(source),
+            Error::Io(x) => Some(x),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(x: std::io::Error) -> Self {
+        Error::Io(x)
+    }
+}
+
+/// libav* errors outside of probing happen while converting.
+impl From<ffmpeg::Error> for Error {
+    fn from(x: ffmpeg::Error) -> Self {
+        Error::Encoder(x.to_string())
+    }
+}
+
+impl From<globset::Error> for Error {
+    fn from(x: globset::Error) -> Self {
+        Error::InvalidArguments(x.to_string())
+    }
+}
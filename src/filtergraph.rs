@@ -0,0 +1,172 @@
+use crate::crop::Crop;
+use crate::error::Error;
+use crate::options::{Denoise, Options, Scaler};
+
+/// Converts HDR video to 8-bit SDR, with BT.709 colours.
+const TONEMAP_FILTER: &str =
+    "zscale=t=linear:npl=100,format=gbrpf32le,zscale=p=bt709,tonemap=tonemap=hable:desat=0,zscale=t=bt709:m=bt709:r=tv,format=yuv420p";
+
+/// Where frames are filtered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Device {
+    Cpu,
+    /// Filters that have a CUDA implementation run on an Nvidia GPU, with frames uploaded to it first.
+    Cuda,
+}
+
+impl Device {
+    /// Where ffmpeg filters video when it encodes with `args`.
+    pub fn for_options(args: &Options) -> Device {
+        if args.gpu {
+            Device::Cuda
+        } else {
+            Device::Cpu
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Deinterlace {
+    Yadif,
+    Bwdif,
+    /// Match fields back into the original frames, deinterlace any that don't match, then drop the duplicated frames.
+    InverseTelecine,
+    /// Only deinterlace the frames that idet finds to be interlaced, leaving progressive sections untouched.
+    InterlacedFramesOnly,
+}
+
+/// A size to scale video to, in pixels, and the algorithm to scale it with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scale {
+    width: u32,
+    height: u32,
+    scaler: Scaler,
+}
+
+impl Scale {
+    /// Both dimensions must be even, so that chroma subsampling lines up.
+    pub fn new(width: u32, height: u32, scaler: Scaler) -> Result<Scale, Error> {
+        if width == 0 || height == 0 || width % 2 != 0 || height % 2 != 0 {
+            return Err(Error::InvalidArguments(format!(
+                "Invalid scale {}x{}, both sides must be even",
+                width, height
+            )));
+        }
+        return Ok(Scale { width, height, scaler });
+    }
+
+    /// The largest size that video of `width` by `height` fits within `max_width` by `max_height` at, keeping its
+    /// aspect ratio, or `None` if it already fits. Video is only ever scaled down.
+    pub fn fit(width: u32, height: u32, max_width: Option<u32>, max_height: Option<u32>, scaler: Scaler) -> Option<Scale> {
+        let max_width = max_width.unwrap_or(u32::MAX);
+        let max_height = max_height.unwrap_or(u32::MAX);
+        if (width <= max_width && height <= max_height) || width == 0 || height == 0 {
+            return None;
+        }
+
+        // The side that is furthest over its limit is scaled to fit it exactly, and the other side in proportion
+        let (new_width, new_height) = if width as u64 * max_height as u64 > height as u64 * max_width as u64 {
+            (max_width, (height as u64 * max_width as u64 / width as u64) as u32)
+        } else {
+            ((width as u64 * max_height as u64 / height as u64) as u32, max_height)
+        };
+        let even = |x: u32| (x & !1).max(2);
+        return Scale::new(even(new_width), even(new_height), scaler).ok();
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+}
+
+/// Whether scale_cuda can be told which algorithm to scale with, which it can from FFmpeg 5 (libavfilter 8). Before
+/// that, video is scaled on the CPU instead, so that the algorithm chosen is still the one used. This assumes the
+/// ffmpeg binary is the same version as the libav* linked in.
+fn scale_cuda_has_interp_algo() -> bool {
+    ffmpeg::filter::version() >> 16 >= 8
+}
+
+/// A step in a video filter graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    Crop(Crop),
+    /// Tone-map HDR video to SDR.
+    Tonemap,
+    Deinterlace(Deinterlace),
+    Denoise(Denoise),
+    Scale(Scale),
+}
+
+impl Filter {
+    /// The filter as libav* expects it, to run on the CPU.
+    fn render_cpu(&self) -> String {
+        match self {
+            Filter::Crop(x) => x.filter(),
+            Filter::Tonemap => TONEMAP_FILTER.to_string(),
+            Filter::Deinterlace(Deinterlace::Yadif) => "yadif".to_string(),
+            Filter::Deinterlace(Deinterlace::Bwdif) => "bwdif".to_string(),
+            Filter::Deinterlace(Deinterlace::InverseTelecine) => "fieldmatch,yadif=deint=interlaced,decimate".to_string(),
+            Filter::Deinterlace(Deinterlace::InterlacedFramesOnly) => "idet,yadif=deint=interlaced".to_string(),
+            Filter::Denoise(x) => format!("hqdn3d={}", x.hqdn3d_params()),
+            Filter::Scale(x) => format!("scale={}:{}:flags={}", x.width, x.height, x.scaler),
+        }
+    }
+
+    /// The CUDA equivalent of the filter, if there is one.
+    fn render_cuda(&self) -> Option<String> {
+        match self {
+            Filter::Deinterlace(Deinterlace::Yadif) => Some("yadif_cuda".to_string()),
+            Filter::Scale(x) if scale_cuda_has_interp_algo() => match x.scaler {
+                Scaler::Bilinear | Scaler::Bicubic | Scaler::Lanczos => Some(format!("scale_cuda={}:{}:interp_algo={}", x.width, x.height, x.scaler)),
+                Scaler::Spline | Scaler::Area => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// The filters applied to a video stream, in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FilterGraph {
+    filters: Vec<Filter>,
+}
+
+impl FilterGraph {
+    pub fn new() -> FilterGraph {
+        FilterGraph::default()
+    }
+
+    pub fn push(&mut self, filter: Filter) {
+        self.filters.push(filter);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// The libav* filters that make up the graph on `device`, in order. On the GPU, frames are uploaded before the
+    /// first of the trailing filters that all have CUDA implementations, so that they never have to be downloaded
+    /// again. Anything before that runs on the CPU.
+    pub fn steps(&self, device: Device) -> Vec<String> {
+        let gpu_start = match device {
+            Device::Cpu => self.filters.len(),
+            Device::Cuda => self.filters.iter().rposition(|x| x.render_cuda().is_none()).map_or(0, |x| x + 1),
+        };
+
+        let mut steps: Vec<String> = self.filters[..gpu_start].iter().map(Filter::render_cpu).collect();
+        if gpu_start < self.filters.len() {
+            steps.push("hwupload_cuda".to_string());
+            steps.extend(self.filters[gpu_start..].iter().filter_map(Filter::render_cuda));
+        }
+        return steps;
+    }
+
+    /// The whole graph on `device`, as a single filter string for ffmpeg's `-filter:v` or for libav*.
+    pub fn render(&self, device: Device) -> String {
+        self.steps(device).join(",")
+    }
+}
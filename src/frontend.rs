@@ -1,12 +1,60 @@
-use crate::interface::Opt;
+use crate::crop::Crop;
+use crate::filtergraph::Scale;
+use crate::interlace::ScanType;
+use crate::language;
+use crate::options::{Options, Tonemap};
+use crate::policy::Target;
 
 pub use ffmpeg::codec;
-pub use ffmpeg::codec::{Context, Parameters};
 pub use ffmpeg::format::context::Input;
 pub use ffmpeg::media::Type;
+pub use ffmpeg::Rational;
 use log::{error, warn};
+use serde::Serialize;
 use std::collections::HashMap;
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::time::Duration;
 
+/// A stream of a file, as libav* reads it.
+pub type RawStream<'a> = ffmpeg::format::stream::Stream<'a>;
+
+/// What is known about a file, and its video, audio and subtitle streams.
+#[derive(Debug, Clone)]
+pub struct MediaInfo {
+    /// The names libav* gives the container format, such as "matroska,webm".
+    pub format_name: String,
+    pub format_long_name: String,
+    /// The duration of the file, if known.
+    pub duration: Option<Duration>,
+    /// The overall bitrate in bits per second, if known.
+    pub bit_rate: Option<u64>,
+    pub title: Option<String>,
+    pub streams: Vec<Stream>,
+}
+
+impl MediaInfo {
+    pub fn new(file: &Input) -> MediaInfo {
+        let format = file.format();
+        // libav* durations are in microseconds
+        let duration = if file.duration() > 0 {
+            Some(Duration::from_micros(file.duration() as u64))
+        } else {
+            None
+        };
+
+        MediaInfo {
+            format_name: format.name().to_string(),
+            format_long_name: format.description().to_string(),
+            duration,
+            bit_rate: if file.bit_rate() > 0 { Some(file.bit_rate() as u64) } else { None },
+            title: title(&file.metadata()),
+            streams: parse_stream_metadata(file),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct StreamMappings {
     pub video: Vec<Stream>,
     pub audio: Vec<Stream>,
@@ -17,9 +65,33 @@ impl StreamMappings {
     pub fn iter(&self) -> impl Iterator<Item = &Stream> {
         self.video.iter().chain(self.audio.iter()).chain(self.subtitle.iter())
     }
+
+    /// The disposition each stream is written with, by input index. The defaults are the first video, the first audio
+    /// that isn't commentary or audio description (so the main audio in the most preferred language), and the first
+    /// forced subtitles, so that players only show subtitles for foreign dialogue unless asked. No other stream is a
+    /// default.
+    pub fn output_dispositions(&self) -> HashMap<usize, Disposition> {
+        let first = |streams: &[Stream], wanted: fn(&Disposition) -> bool| streams.iter().find(|x| wanted(&x.disposition())).map(Stream::index);
+        let defaults = [
+            self.video.first().map(Stream::index),
+            first(&self.audio, |x| !x.comment && !x.visual_impaired).or_else(|| self.audio.first().map(Stream::index)),
+            first(&self.subtitle, |x| x.forced),
+        ];
+
+        return self
+            .iter()
+            .map(|stream| {
+                let disposition = Disposition {
+                    default: defaults.contains(&Some(stream.index())),
+                    ..stream.disposition()
+                };
+                (stream.index(), disposition)
+            })
+            .collect();
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Stream {
     Video(Video),
     Audio(Audio),
@@ -42,6 +114,206 @@ impl Stream {
             Stream::Subtitle(x) => x.codec,
         }
     }
+
+    /// The name libav* gives the stream's profile, such as "High 10" or "DTS-HD MA".
+    pub fn profile_name(&self) -> Option<String> {
+        let profile = match self {
+            Stream::Video(x) => x.profile,
+            Stream::Audio(x) => x.profile,
+            Stream::Subtitle(_) => None,
+        }?;
+        let name = unsafe { ffmpeg::ffi::avcodec_profile_name(self.codec().into(), profile.into()) };
+        if name.is_null() {
+            return None;
+        }
+        return Some(unsafe { CStr::from_ptr(name) }.to_string_lossy().into_owned());
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        match self {
+            Stream::Video(x) => x.title.as_deref(),
+            Stream::Audio(x) => x.title.as_deref(),
+            Stream::Subtitle(x) => x.title.as_deref(),
+        }
+    }
+
+    pub fn disposition(&self) -> Disposition {
+        match self {
+            Stream::Video(x) => x.disposition,
+            Stream::Audio(x) => x.disposition,
+            Stream::Subtitle(x) => x.disposition,
+        }
+    }
+
+    /// The ISO 639-2/B code of the stream's language, if it is known.
+    pub fn lang(&self) -> Option<&str> {
+        match self {
+            Stream::Video(_) => None,
+            Stream::Audio(x) => x.lang.as_deref(),
+            Stream::Subtitle(x) => x.lang.as_deref(),
+        }
+    }
+
+    pub fn as_video(&self) -> Option<&Video> {
+        match self {
+            Stream::Video(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    pub fn channels(&self) -> Option<u16> {
+        match self {
+            Stream::Audio(x) => Some(x.channels),
+            _ => None,
+        }
+    }
+
+    /// In bits per second, if known.
+    pub fn bit_rate(&self) -> Option<u64> {
+        match self {
+            Stream::Video(x) => x.bit_rate,
+            Stream::Audio(x) => x.bit_rate,
+            Stream::Subtitle(_) => None,
+        }
+    }
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Many containers don't store the bitrate of each stream, in which case libav* reports 0.
+fn known_bit_rate(bit_rate: usize) -> Option<u64> {
+    if bit_rate > 0 {
+        Some(bit_rate as u64)
+    } else {
+        None
+    }
+}
+
+/// Streams may be titled with a description, such as "Director's Commentary".
+fn title(metadata: &ffmpeg::util::dictionary::Ref<'_>) -> Option<String> {
+    return metadata.get("title").map(str::trim).filter(|x| !x.is_empty()).map(str::to_string);
+}
+
+/// libav* reports unknown rates as 0/0 or 0/1.
+fn known_rational(x: Rational) -> Option<Rational> {
+    if x.numerator() > 0 && x.denominator() > 0 {
+        Some(x)
+    } else {
+        None
+    }
+}
+
+/// Phrases in a stream's title that say what it is for, for files that say so in titles rather than in flags.
+const COMMENTARY_PHRASES: &[&str] = &["commentary"];
+const HEARING_IMPAIRED_PHRASES: &[&str] = &["sdh", "cc", "closed captions", "hearing impaired", "hard of hearing", "deaf"];
+const VISUAL_IMPAIRED_PHRASES: &[&str] = &["audio description", "described", "descriptive"];
+const FORCED_PHRASES: &[&str] = &["forced", "foreign parts", "foreign dialogue", "signs"];
+/// Words that say a title means the opposite of the phrase that follows them, as in "Non-Forced" or "Not forced".
+const NEGATIONS: &[&str] = &["non", "not", "no"];
+
+/// Whether a title contains any of the phrases, as whole words and ignoring case and punctuation. Phrases that come
+/// straight after a negation don't count.
+fn title_mentions(title: &str, phrases: &[&str]) -> bool {
+    let words: Vec<String> = title
+        .split(|x: char| !x.is_alphanumeric())
+        .filter(|x| !x.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    return phrases.iter().any(|phrase| {
+        let phrase: Vec<&str> = phrase.split(' ').collect();
+        words.windows(phrase.len()).enumerate().any(|(i, x)| {
+            let negated = i > 0 && NEGATIONS.contains(&words[i - 1].as_str());
+            x == phrase.as_slice() && !negated
+        })
+    });
+}
+
+/// The flags a container sets on a stream to say what it is for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Disposition {
+    /// Played unless the user picks another stream.
+    pub default: bool,
+    /// Subtitles that are shown even when subtitles are off, such as for foreign dialogue.
+    pub forced: bool,
+    pub original: bool,
+    pub dub: bool,
+    pub comment: bool,
+    pub hearing_impaired: bool,
+    pub visual_impaired: bool,
+}
+
+impl Disposition {
+    fn from_flags(flags: ffmpeg::format::stream::Disposition) -> Disposition {
+        use ffmpeg::format::stream::Disposition as Flags;
+        Disposition {
+            default: flags.contains(Flags::DEFAULT),
+            forced: flags.contains(Flags::FORCED),
+            original: flags.contains(Flags::ORIGINAL),
+            dub: flags.contains(Flags::DUB),
+            comment: flags.contains(Flags::COMMENT),
+            hearing_impaired: flags.contains(Flags::HEARING_IMPAIRED),
+            visual_impaired: flags.contains(Flags::VISUAL_IMPAIRED),
+        }
+    }
+
+    /// Add the flags that a stream's title implies, such as "Commentary" or "English (SDH)".
+    fn with_title(self, title: Option<&str>) -> Disposition {
+        let title = match title {
+            Some(x) => x,
+            None => return self,
+        };
+        Disposition {
+            comment: self.comment || title_mentions(title, COMMENTARY_PHRASES),
+            hearing_impaired: self.hearing_impaired || title_mentions(title, HEARING_IMPAIRED_PHRASES),
+            visual_impaired: self.visual_impaired || title_mentions(title, VISUAL_IMPAIRED_PHRASES),
+            forced: self.forced || title_mentions(title, FORCED_PHRASES),
+            ..self
+        }
+    }
+
+    /// The value of ffmpeg's `-disposition` for these flags, such as "default+forced", or "0" if none are set.
+    pub fn to_ffmpeg(&self) -> String {
+        let names = self.names();
+        if names.is_empty() {
+            return "0".to_string();
+        }
+        return names.join("+");
+    }
+
+    /// The flags, as libav* stores them on a stream.
+    pub fn to_flags(&self) -> ffmpeg::format::stream::Disposition {
+        use ffmpeg::format::stream::Disposition as Flags;
+        let flags = [
+            (self.default, Flags::DEFAULT),
+            (self.forced, Flags::FORCED),
+            (self.original, Flags::ORIGINAL),
+            (self.dub, Flags::DUB),
+            (self.comment, Flags::COMMENT),
+            (self.hearing_impaired, Flags::HEARING_IMPAIRED),
+            (self.visual_impaired, Flags::VISUAL_IMPAIRED),
+        ];
+        return flags.iter().filter(|x| x.0).fold(Flags::empty(), |acc, x| acc | x.1);
+    }
+
+    /// The names ffmpeg gives the flags that are set, such as "default" or "hearing_impaired".
+    pub fn names(&self) -> Vec<&'static str> {
+        let flags = [
+            (self.default, "default"),
+            (self.forced, "forced"),
+            (self.original, "original"),
+            (self.dub, "dub"),
+            (self.comment, "comment"),
+            (self.hearing_impaired, "hearing_impaired"),
+            (self.visual_impaired, "visual_impaired"),
+        ];
+        return flags.iter().filter(|x| x.0).map(|x| x.1).collect();
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -51,18 +323,122 @@ pub enum FieldOrder {
     Interlaced,
 }
 
+/// The colour volume of the display an HDR video was mastered on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MasteringDisplay {
+    /// The CIE 1931 xy chromaticity of the red, green and blue primaries.
+    pub primaries: [(f64, f64); 3],
+    pub white_point: (f64, f64),
+    /// In cd/m².
+    pub min_luminance: f64,
+    pub max_luminance: f64,
+}
+
+/// The brightest pixel, and the brightest average frame, of an HDR video. Both in cd/m².
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentLight {
+    pub max_cll: u32,
+    pub max_fall: u32,
+}
+
+impl MasteringDisplay {
+    /// Parse libav*'s `AVMasteringDisplayMetadata`, which is 20 `AVRational`s (R, G and B primaries, white point, min
+    /// and max luminance) followed by flags saying whether the primaries and luminance are set.
+    fn from_side_data(data: &[u8]) -> Option<MasteringDisplay> {
+        let ints: Vec<i32> = data.chunks_exact(4).map(|x| i32::from_ne_bytes([x[0], x[1], x[2], x[3]])).collect();
+        if ints.len() < 22 || ints[20] == 0 || ints[21] == 0 {
+            return None;
+        }
+        let rational = |i: usize| if ints[i + 1] == 0 { 0.0 } else { ints[i] as f64 / ints[i + 1] as f64 };
+        let point = |i: usize| (rational(i), rational(i + 2));
+
+        return Some(MasteringDisplay {
+            primaries: [point(0), point(4), point(8)],
+            white_point: point(12),
+            min_luminance: rational(16),
+            max_luminance: rational(18),
+        });
+    }
+}
+
+impl ContentLight {
+    /// Parse libav*'s `AVContentLightMetadata`, which is two unsigned ints.
+    fn from_side_data(data: &[u8]) -> Option<ContentLight> {
+        if data.len() < 8 {
+            return None;
+        }
+        return Some(ContentLight {
+            max_cll: u32::from_ne_bytes([data[0], data[1], data[2], data[3]]),
+            max_fall: u32::from_ne_bytes([data[4], data[5], data[6], data[7]]),
+        });
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Video {
     pub index: usize,
     pub codec: codec::Id,
+    pub title: Option<String>,
+    pub disposition: Disposition,
+    /// In pixels, or 0 if unknown.
+    pub width: u32,
+    pub height: u32,
+    /// The shape of each pixel, if known. Anything other than 1:1 is anamorphic.
+    pub sample_aspect_ratio: Option<Rational>,
+    /// The average frame rate, and the lowest rate that all timestamps fit (ffprobe's r_frame_rate). They differ for
+    /// variable frame rate video.
+    pub avg_frame_rate: Option<Rational>,
+    pub real_frame_rate: Option<Rational>,
     pub field_order: FieldOrder,
+    pub profile: Option<ffmpeg::codec::Profile>,
+    pub bit_rate: Option<u64>,
+    /// The name libav* gives the pixel format, such as "yuv420p10le".
+    pub pix_fmt: Option<String>,
+    /// The number of bits per sample.
+    pub bit_depth: Option<u8>,
+    /// The names libav* gives the colour properties, or `None` if they are unspecified.
+    pub color_primaries: Option<String>,
+    pub color_transfer: Option<String>,
+    pub color_space: Option<String>,
+    pub color_range: Option<String>,
+    pub mastering_display: Option<MasteringDisplay>,
+    pub content_light: Option<ContentLight>,
+    /// How the video is scanned, if its frames have been analysed.
+    pub scan: Option<ScanType>,
 }
 
 impl Video {
-    pub fn new(index: usize, codec_context: Context, codec_par: Parameters) -> Video {
-        let codec = codec_par.id();
+    pub fn new(stream: &RawStream) -> Video {
+        let index = stream.index();
+        let codec = stream.parameters().id();
 
-        let decoder = codec_context.decoder().video();
+        let mut mastering_display = None;
+        let mut content_light = None;
+        for x in stream.side_data() {
+            match x.kind() {
+                codec::packet::side_data::Type::MasteringDisplayMetadata => mastering_display = MasteringDisplay::from_side_data(x.data()),
+                codec::packet::side_data::Type::ContentLightLevel => content_light = ContentLight::from_side_data(x.data()),
+                _ => {}
+            }
+        }
+
+        let decoder = stream.codec().decoder().video();
+        let profile = match decoder.as_ref().map(|x| x.profile()) {
+            Ok(codec::Profile::Unknown) => None,
+            Ok(x) => Some(x),
+            Err(_) => None,
+        };
+        let bit_rate = decoder.as_ref().ok().and_then(|x| known_bit_rate(x.bit_rate()));
+        let (width, height) = decoder.as_ref().map_or((0, 0), |x| (x.width(), x.height()));
+        let sample_aspect_ratio = decoder.as_ref().ok().and_then(|x| known_rational(x.aspect_ratio()));
+        let pix_fmt = decoder.as_ref().ok().and_then(|x| x.format().descriptor());
+        let bit_depth = pix_fmt.map(|x| unsafe { (*x.as_ptr()).comp[0].depth as u8 });
+        let pix_fmt = pix_fmt.map(|x| x.name().to_string());
+        let name = |x: Option<&str>| x.map(str::to_string);
+        let color_primaries = decoder.as_ref().ok().and_then(|x| name(x.color_primaries().name()));
+        let color_transfer = decoder.as_ref().ok().and_then(|x| name(x.color_transfer_characteristic().name()));
+        let color_space = decoder.as_ref().ok().and_then(|x| name(x.color_space().name()));
+        let color_range = decoder.as_ref().ok().and_then(|x| name(x.color_range().name()));
         let field_order = match unsafe { decoder.map(|x| (*x.as_ptr()).field_order) } {
             Ok(ffmpeg::ffi::AVFieldOrder::AV_FIELD_PROGRESSIVE) => FieldOrder::Progressive,
             Ok(ffmpeg::ffi::AVFieldOrder::AV_FIELD_TT) => FieldOrder::Interlaced,
@@ -76,7 +452,85 @@ impl Video {
             }
         };
 
-        Video { index, codec, field_order }
+        Video {
+            index,
+            codec,
+            title: title(&stream.metadata()),
+            disposition: Disposition::from_flags(stream.disposition()),
+            width,
+            height,
+            sample_aspect_ratio,
+            avg_frame_rate: known_rational(stream.avg_frame_rate()),
+            real_frame_rate: known_rational(stream.rate()),
+            field_order,
+            profile,
+            bit_rate,
+            pix_fmt,
+            bit_depth,
+            color_primaries,
+            color_transfer,
+            color_space,
+            color_range,
+            mastering_display,
+            content_light,
+            scan: None,
+        }
+    }
+
+    /// The shape of the picture as it is displayed, taking non-square pixels into account.
+    pub fn display_aspect_ratio(&self) -> Option<Rational> {
+        if self.width == 0 || self.height == 0 {
+            return None;
+        }
+        let sar = self.sample_aspect_ratio.unwrap_or_else(|| Rational::new(1, 1));
+        let width = self.width as i64 * sar.numerator() as i64;
+        let height = self.height as i64 * sar.denominator() as i64;
+        let gcd = gcd(width, height);
+        return Some(Rational::new((width / gcd) as i32, (height / gcd) as i32));
+    }
+
+    /// Whether the video uses an HDR transfer function, PQ (as in HDR10) or HLG.
+    pub fn is_hdr(&self) -> bool {
+        match self.color_transfer.as_deref() {
+            Some("smpte2084") | Some("arib-std-b67") => true,
+            _ => false,
+        }
+    }
+
+    /// The size to scale the video down to, once it is cropped to `crop`, so that it fits within the maximum size in
+    /// `args`. Returns `None` if it already fits.
+    pub fn downscale(&self, crop: Option<Crop>, args: &Options) -> Option<Scale> {
+        let (width, height) = crop.map_or((self.width, self.height), |x| (x.width, x.height));
+        return Scale::fit(width, height, args.max_width, args.max_height, args.scaler);
+    }
+}
+
+/// A stream that nothing is known about, to build streams from without probing a file.
+impl Default for Video {
+    fn default() -> Video {
+        Video {
+            index: 0,
+            codec: codec::Id::None,
+            title: None,
+            disposition: Disposition::default(),
+            width: 0,
+            height: 0,
+            sample_aspect_ratio: None,
+            avg_frame_rate: None,
+            real_frame_rate: None,
+            field_order: FieldOrder::Unknown,
+            profile: None,
+            bit_rate: None,
+            pix_fmt: None,
+            bit_depth: None,
+            color_primaries: None,
+            color_transfer: None,
+            color_space: None,
+            color_range: None,
+            mastering_display: None,
+            content_light: None,
+            scan: None,
+        }
     }
 }
 
@@ -85,21 +539,80 @@ pub struct Audio {
     pub index: usize,
     pub codec: codec::Id,
     pub lang: Option<String>,
+    pub title: Option<String>,
+    pub disposition: Disposition,
     pub profile: Option<ffmpeg::codec::Profile>,
+    pub channels: u16,
+    /// The name libav* gives the channel layout, such as "5.1(side)", if it is known.
+    pub channel_layout: Option<String>,
+    /// In Hz, or 0 if unknown.
+    pub sample_rate: u32,
+    pub bit_rate: Option<u64>,
 }
 
 impl Audio {
-    pub fn new(index: usize, codec_context: Context, codec_par: Parameters, metadata: ffmpeg::util::dictionary::Ref<'_>) -> Audio {
-        let codec = codec_par.id();
-        let lang = metadata.get("language").map(|f| f.to_string());
-        let decoder = codec_context.decoder().audio();
-        let profile = match decoder.map(|x| x.profile()) {
+    pub fn new(stream: &RawStream) -> Audio {
+        let index = stream.index();
+        let codec = stream.parameters().id();
+        let metadata = stream.metadata();
+        let lang = metadata.get("language").and_then(language::normalize);
+        let title = title(&metadata);
+        let decoder = stream.codec().decoder().audio();
+        let profile = match decoder.as_ref().map(|x| x.profile()) {
             Ok(codec::Profile::Unknown) => None,
             Ok(x) => Some(x),
             Err(_) => None,
         };
+        let channels = decoder.as_ref().map(|x| x.channels()).unwrap_or(0);
+        let channel_layout = decoder
+            .as_ref()
+            .ok()
+            .and_then(|x| channel_layout_name(x.channel_layout().bits(), channels));
+        let sample_rate = decoder.as_ref().map(|x| x.rate()).unwrap_or(0);
+        let bit_rate = decoder.as_ref().ok().and_then(|x| known_bit_rate(x.bit_rate()));
+
+        Audio {
+            index,
+            codec,
+            lang,
+            disposition: Disposition::from_flags(stream.disposition()).with_title(title.as_deref()),
+            title,
+            profile,
+            channels,
+            channel_layout,
+            sample_rate,
+            bit_rate,
+        }
+    }
+}
 
-        Audio { index, codec, lang, profile }
+/// Many containers don't store a channel layout, in which case libav* reports none.
+fn channel_layout_name(layout: u64, channels: u16) -> Option<String> {
+    if layout == 0 {
+        return None;
+    }
+    let mut buffer = [0 as c_char; 64];
+    unsafe {
+        ffmpeg::ffi::av_get_channel_layout_string(buffer.as_mut_ptr(), buffer.len() as i32, channels.into(), layout);
+        return Some(CStr::from_ptr(buffer.as_ptr()).to_string_lossy().into_owned());
+    }
+}
+
+/// A stream that nothing is known about, to build streams from without probing a file.
+impl Default for Audio {
+    fn default() -> Audio {
+        Audio {
+            index: 0,
+            codec: codec::Id::None,
+            lang: None,
+            title: None,
+            disposition: Disposition::default(),
+            profile: None,
+            channels: 0,
+            channel_layout: None,
+            sample_rate: 0,
+            bit_rate: None,
+        }
     }
 }
 
@@ -108,34 +621,53 @@ pub struct Subtitle {
     pub index: usize,
     pub codec: codec::Id,
     pub lang: Option<String>,
+    pub title: Option<String>,
+    pub disposition: Disposition,
 }
 
 impl Subtitle {
-    pub fn new(index: usize, codec_par: Parameters, metadata: ffmpeg::util::dictionary::Ref<'_>) -> Subtitle {
-        let codec = codec_par.id();
-        let lang = metadata.get("language").map(|f| f.to_string());
+    pub fn new(stream: &RawStream) -> Subtitle {
+        let codec = stream.parameters().id();
+        let metadata = stream.metadata();
+        let lang = metadata.get("language").and_then(language::normalize);
+        let title = title(&metadata);
+
+        Subtitle {
+            index: stream.index(),
+            codec,
+            lang,
+            disposition: Disposition::from_flags(stream.disposition()).with_title(title.as_deref()),
+            title,
+        }
+    }
+}
 
-        Subtitle { index, codec, lang }
+/// A stream that nothing is known about, to build streams from without probing a file.
+impl Default for Subtitle {
+    fn default() -> Subtitle {
+        Subtitle {
+            index: 0,
+            codec: codec::Id::None,
+            lang: None,
+            title: None,
+            disposition: Disposition::default(),
+        }
     }
 }
 
 pub fn parse_stream_metadata(file: &Input) -> Vec<Stream> {
     let mut out: Vec<Stream> = Vec::new();
     for stream in file.streams() {
-        let index = stream.index();
-        let codec_context = stream.codec();
-        let codec_parameters = stream.parameters();
-        let tags = stream.metadata();
         //let explode = codec.codec().unwrap();
-        match codec_context.medium() {
+        match stream.codec().medium() {
             Type::Video => {
-                out.push(Stream::Video(Video::new(index, codec_context, codec_parameters)));
+                out.push(Stream::Video(Video::new(&stream)));
             }
             Type::Audio => {
-                out.push(Stream::Audio(Audio::new(index, codec_context, codec_parameters, tags)));
+                out.push(Stream::Audio(Audio::new(&stream)));
             }
             Type::Subtitle => {
-                out.push(Stream::Subtitle(Subtitle::new(index, codec_parameters, tags)));
+                out.push(Stream::Subtitle(Subtitle::new(&stream)));
             }
             _ => {}
         };
@@ -143,30 +675,38 @@ pub fn parse_stream_metadata(file: &Input) -> Vec<Stream> {
     return out;
 }
 
-pub fn get_stream_mappings(parsed: &[Stream], args: &Opt) -> StreamMappings {
+/// The position of a stream's normalised language in a preference list, or `None` if it isn't in the list. Streams
+/// whose language is unknown match "und".
+fn language_preference(lang: Option<&str>, preferred: &[String]) -> Option<usize> {
+    return preferred.iter().position(|x| language::normalize(x).as_deref() == lang);
+}
+
+/// Keep the streams in a preferred language, ordered by preference and then by their order in the file. If none of
+/// the streams are in a preferred language, or `all_streams` is set, every stream is kept, with those in a preferred
+/// language first.
+fn select_by_language(streams: Vec<(Option<&str>, Stream)>, preferred: &[String], all_streams: bool) -> Vec<Stream> {
+    let mut ranked: Vec<(Option<usize>, Stream)> = streams
+        .into_iter()
+        .map(|(lang, stream)| (language_preference(lang, preferred), stream))
+        .collect();
+    if !all_streams && ranked.iter().any(|x| x.0.is_some()) {
+        ranked.retain(|x| x.0.is_some());
+    }
+    // The sort is stable, so streams in the same language keep their order
+    ranked.sort_by_key(|x| x.0.unwrap_or(usize::MAX));
+    return ranked.into_iter().map(|x| x.1).collect();
+}
+
+pub fn get_stream_mappings(parsed: &[Stream], args: &Options) -> StreamMappings {
     let mut video: Vec<Stream> = Vec::new();
-    let mut audio: Vec<Stream> = Vec::new();
-    let mut subtitle: Vec<Stream> = Vec::new();
-    //let mut audio_mappings: Vec<usize> = Vec::new();
-    //let mut subtitle_mappings: Vec<usize> = Vec::new();
+    let mut audio = Vec::new();
+    let mut subtitle = Vec::new();
 
     for stream in parsed {
         match stream {
-            Stream::Video(x) => {
-                video.push(Stream::Video(x.clone()));
-            }
-            Stream::Audio(x) => {
-                if x.lang == Some("eng".to_string()) || args.all_streams {
-                    audio.push(Stream::Audio(x.clone()));
-                    //audio_mappings.push(audio.index);
-                }
-            }
-            Stream::Subtitle(x) => {
-                if x.lang == Some("eng".to_string()) || args.all_streams {
-                    subtitle.push(Stream::Subtitle(x.clone()));
-                    //subtitle_mappings.push(subtitle.index);
-                }
-            }
+            Stream::Video(x) => video.push(Stream::Video(x.clone())),
+            Stream::Audio(x) => audio.push((x.lang.as_deref(), Stream::Audio(x.clone()))),
+            Stream::Subtitle(x) => subtitle.push((x.lang.as_deref(), Stream::Subtitle(x.clone()))),
         }
     }
 
@@ -176,60 +716,91 @@ pub fn get_stream_mappings(parsed: &[Stream], args: &Opt) -> StreamMappings {
         //return Err(SimpleError::new(format!("File has {} video streams", num_vids)));
     }
 
-    if audio.len() == 0 {
-        // if no english streams are detected, just use all streams
-        for stream in parsed {
-            match stream {
-                Stream::Audio(x) => {
-                    audio.push(Stream::Audio(x.clone()));
-                }
-                _ => {}
-            }
-        }
-    }
-
-    if subtitle.len() == 0 {
-        // if no english streams are detected, just use all streams
-        for stream in parsed.iter() {
-            match stream {
-                Stream::Subtitle(x) => {
-                    subtitle.push(Stream::Subtitle(x.clone()));
-                }
-                _ => {}
-            }
-        }
+    StreamMappings {
+        video,
+        audio: select_by_language(audio, &args.audio_langs, args.all_streams),
+        subtitle: select_by_language(subtitle, &args.sub_langs, args.all_streams),
     }
-
-    StreamMappings { video, audio, subtitle }
 }
 
-pub fn get_codec_mapping(stream_mappings: &StreamMappings, args: &crate::interface::Opt) -> HashMap<usize, Option<codec::Id>> {
-    use codec::Id::{AAC, DTS, DVD_SUBTITLE, FLAC, H264, HDMV_PGS_SUBTITLE, HEVC, SSA, TRUEHD};
+/// Decide what each stream is encoded to, according to the codec policy, when video is cropped to `crop`. Streams
+/// mapped to `None` are copied.
+pub fn get_codec_mapping(stream_mappings: &StreamMappings, crop: Option<Crop>, args: &Options) -> HashMap<usize, Option<Target>> {
+    let policy = &args.policy;
 
     stream_mappings
         .iter()
         .map(|stream| {
-            let index = stream.index();
-            match stream {
-                Stream::Video(video) => match video.codec {
-                    HEVC | H264 => (index, None),
-                    _ => (index, Some(if args.gpu { HEVC } else { H264 })),
-                },
-                Stream::Audio(audio) => match audio.codec {
-                    FLAC | AAC => (index, None),
-
-                    TRUEHD => (index, Some(FLAC)),
-                    DTS => match audio.profile {
-                        Some(codec::Profile::DTS(codec::profile::DTS::HD_MA)) => (index, Some(FLAC)),
-                        _ => (index, Some(AAC)),
-                    },
-                    _ => (index, Some(AAC)),
-                },
-                Stream::Subtitle(subtitle) => match subtitle.codec {
-                    HDMV_PGS_SUBTITLE | DVD_SUBTITLE => (index, None),
-                    _ => (index, Some(SSA)),
-                },
-            }
+            let target = match stream {
+                Stream::Video(x) => {
+                    // HDR video can't be copied if it is to be tone-mapped, nor can video that is too large
+                    let tonemap = args.tonemap == Some(Tonemap::Replace) && x.is_hdr();
+                    let downscale = x.downscale(crop, args).is_some();
+                    let target = policy.video.decide(stream, args.force_reencode || tonemap || downscale);
+                    let replacement = if args.gpu {
+                        policy.video.gpu.clone()
+                    } else {
+                        args.video_codec.map(|x| x.target())
+                    };
+                    match replacement {
+                        Some(x) => target.map(|_| x),
+                        None => target,
+                    }
+                }
+                Stream::Audio(_) => policy.audio.decide(stream, false),
+                Stream::Subtitle(_) => policy.subtitle.decide(stream, false),
+            };
+            (stream.index(), target)
         })
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn titles_mention_phrases() {
+        let cases: &[(&str, &[&str], bool)] = &[
+            ("Forced", FORCED_PHRASES, true),
+            ("English Forced Subs", FORCED_PHRASES, true),
+            ("English (Forced)", FORCED_PHRASES, true),
+            ("FORCED", FORCED_PHRASES, true),
+            ("Signs & Songs", FORCED_PHRASES, true),
+            ("Foreign Parts Only", FORCED_PHRASES, true),
+            ("English (Non-Forced)", FORCED_PHRASES, false),
+            ("Non Forced", FORCED_PHRASES, false),
+            ("Full, not forced", FORCED_PHRASES, false),
+            ("Full Subtitles", FORCED_PHRASES, false),
+            ("Enforced Silence", FORCED_PHRASES, false),
+            ("Foreign", FORCED_PHRASES, false),
+            ("English [SDH]", HEARING_IMPAIRED_PHRASES, true),
+            ("English (CC)", HEARING_IMPAIRED_PHRASES, true),
+            ("Closed Captions", HEARING_IMPAIRED_PHRASES, true),
+            ("English, Non-SDH", HEARING_IMPAIRED_PHRASES, false),
+            ("Accent", HEARING_IMPAIRED_PHRASES, false),
+            ("Director's Commentary", COMMENTARY_PHRASES, true),
+            ("Commentary with Cast and Crew", COMMENTARY_PHRASES, true),
+            ("English 5.1", COMMENTARY_PHRASES, false),
+            ("English (Descriptive Audio)", VISUAL_IMPAIRED_PHRASES, true),
+            ("Audio Description", VISUAL_IMPAIRED_PHRASES, true),
+            ("", FORCED_PHRASES, false),
+        ];
+        for (title, phrases, expected) in cases {
+            assert_eq!(title_mentions(title, phrases), *expected, "{}", title);
+        }
+    }
+
+    #[test]
+    fn titles_add_to_dispositions() {
+        let disposition = Disposition::default().with_title(Some("English (SDH) Forced"));
+        assert!(disposition.hearing_impaired && disposition.forced && !disposition.comment);
+
+        let disposition = Disposition {
+            forced: true,
+            ..Disposition::default()
+        };
+        assert_eq!(disposition.with_title(Some("English (Non-Forced)")), disposition);
+        assert_eq!(disposition.with_title(None), disposition);
+    }
+}
@@ -1,7 +1,10 @@
 use crate::util;
 
 use clap::arg_enum;
+use std::path::PathBuf;
 pub use structopt::StructOpt;
+use videoconverter::options::{Denoise, Libx264Tune, Scaler, TVOptions, Tonemap, VideoCodec};
+use videoconverter::Error;
 
 #[derive(StructOpt, Debug)]
 #[structopt(setting(clap::AppSettings::ColoredHelp))]
@@ -11,14 +14,28 @@ pub struct Opt {
     #[structopt(short, long)]
     pub all_streams: bool,
 
-    /// Specify a CRF value to be passed to libx264
-    #[structopt(long, default_value = "20")]
-    pub crf: u8,
+    /// Keep audio in these languages, in order of preference, for example 'jpn,eng'. 'und' matches audio with no
+    /// language. If none of the audio is in these languages, all of it is kept. Defaults to eng
+    #[structopt(long, use_delimiter = true)]
+    pub audio_langs: Vec<String>,
 
-    /// Specify a crop filter. These are of the format 'crop=height:width:x:y'
+    /// Keep subtitles in these languages, in order of preference, in the same way as --audio-langs. Defaults to eng
+    #[structopt(long, use_delimiter = true)]
+    pub sub_langs: Vec<String>,
+
+    /// Specify a CRF value to be passed to libx264. Defaults to 20
+    #[structopt(long)]
+    pub crf: Option<u8>,
+
+    /// Specify a crop filter. These are of the format 'crop=width:height:x:y'
     #[structopt(long)]
     pub crop: Option<String>,
 
+    /// Detect black borders by sampling frames from across each file, and crop them off. Like --crop, this only
+    /// applies when video is re-encoded
+    #[structopt(long, conflicts_with = "crop")]
+    pub auto_crop: bool,
+
     /// Force deinterlacing of video
     #[structopt(short = "-d", long)]
     pub force_deinterlace: bool,
@@ -27,6 +44,27 @@ pub struct Opt {
     #[structopt(short = "-D", long, conflicts_with = "force_deinterlace")]
     pub no_deinterlace: bool,
 
+    /// Analyse sampled frames to tell progressive, interlaced, telecined and mixed video apart, and deinterlace each
+    /// with a suitable filter (bwdif, inverse telecine or yadif) rather than trusting the field order
+    #[structopt(long, conflicts_with = "no_deinterlace")]
+    pub detect_interlace: bool,
+
+    /// Denoise video with hqdn3d before it is encoded, which makes noisy sources compress better
+    #[structopt(long, possible_values = Denoise::VARIANTS, case_insensitive = true)]
+    pub denoise: Option<Denoise>,
+
+    /// Scale down video wider than this, keeping its aspect ratio. Video that is scaled is always re-encoded
+    #[structopt(long)]
+    pub max_width: Option<u32>,
+
+    /// Scale down video taller than this, keeping its aspect ratio. Video that is scaled is always re-encoded
+    #[structopt(long)]
+    pub max_height: Option<u32>,
+
+    /// The algorithm to scale video with. Defaults to bicubic
+    #[structopt(long, possible_values = Scaler::VARIANTS, case_insensitive = true)]
+    pub scaler: Option<Scaler>,
+
     /// Force reencoding of video
     #[structopt(long)]
     pub force_reencode: bool,
@@ -35,45 +73,119 @@ pub struct Opt {
     #[structopt(short, long, conflicts_with = "no_hwaccel")]
     pub gpu: bool,
 
+    /// Encode video with this software encoder, rather than following the codec policy. The CRF is converted to the
+    /// encoder's equivalent
+    #[structopt(long, possible_values = VideoCodec::VARIANTS, case_insensitive = true, conflicts_with = "gpu")]
+    pub video_codec: Option<VideoCodec>,
+
+    /// Tone-map HDR video to SDR, for devices that can't play HDR. 'replace' writes only the SDR version, while
+    /// 'alongside' writes it to a second file, next to the HDR one
+    #[structopt(long, possible_values = Tonemap::VARIANTS, case_insensitive = true)]
+    pub tonemap: Option<Tonemap>,
+
     /// Disable hardware-accelerated decoding
     #[structopt(long)]
     pub no_hwaccel: bool,
 
+    /// Convert in-process using libav*, rather than by running the ffmpeg binary
+    #[structopt(long)]
+    pub native: bool,
+
+    /// The maximum number of files to convert at once. Defaults to 1
+    #[structopt(short, long)]
+    pub jobs: Option<usize>,
+
+    /// The maximum number of remuxes (files where every stream is copied) to run at once. Defaults to the value of --jobs
+    #[structopt(long)]
+    pub remux_jobs: Option<usize>,
+
+    /// The maximum number of re-encodes to run at once. Defaults to 1
+    #[structopt(long)]
+    pub encode_jobs: Option<usize>,
+
+    /// Also convert files in subdirectories, mirroring the directory structure into the output directory
+    #[structopt(short, long)]
+    pub recursive: bool,
+
+    /// Use a named preset, bundling the encoder, CRF, tune and filters for a type of source. Other arguments override
+    /// the values it sets
+    #[structopt(long)]
+    pub preset: Option<String>,
+
+    /// A TOML file defining more presets, in addition to the built-in ones
+    #[structopt(long)]
+    pub preset_file: Option<PathBuf>,
+
+    /// A TOML file deciding which codecs are copied, and what everything else is encoded to
+    #[structopt(long)]
+    pub codec_policy: Option<PathBuf>,
+
+    /// Only convert files matching this glob. May be given more than once
+    #[structopt(long, number_of_values = 1)]
+    pub include: Vec<String>,
+
+    /// Skip files and directories matching this glob, as well as those excluded by the configuration (by default,
+    /// images, text files and archives). May be given more than once
+    #[structopt(long, number_of_values = 1)]
+    pub exclude: Vec<String>,
+
+    /// Stop converting files after the first failure, rather than continuing with the rest of the batch
+    #[structopt(long)]
+    pub fail_fast: bool,
+
+    /// Print the plan for each file to stdout in the given format, instead of converting anything
+    #[structopt(long, possible_values = &PlanFormat::variants(), case_insensitive = true)]
+    pub plan: Option<PlanFormat>,
+
     /// Do not actually perform the conversion
     #[structopt(short, long)]
     pub simulate: bool,
 
-    /// Specify libx264 tune.
-    #[structopt(short, long, possible_values = &Libx264Tune::variants(), case_insensitive=true, conflicts_with = "gpu")]
+    /// Specify libx264 tune. Other software encoders use the nearest equivalent, if there is one
+    #[structopt(short, long, possible_values = Libx264Tune::VARIANTS, case_insensitive=true, conflicts_with = "gpu")]
     pub tune: Option<Libx264Tune>,
 
     /// The path to operate on
     #[structopt(default_value = ".")]
-    pub path: std::path::PathBuf,
+    pub path: PathBuf,
+
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
 }
 
-arg_enum! {
-    #[derive(Debug)]
-    pub enum Libx264Tune {
-        Film,
-        Animation,
-        Grain,
-        Stillimage,
-        Psnr,
-        Ssim,
-        Fastdecode,
-        Zerolatency,
-    }
+#[derive(StructOpt, Debug)]
+pub enum Command {
+    /// Inspect the configuration
+    Config(ConfigCommand),
+    /// Print the streams of files as the converter sees them, and what converting each file would do to them
+    Probe {
+        /// The files to probe
+        #[structopt(required = true)]
+        paths: Vec<PathBuf>,
+        /// Print JSON rather than a table
+        #[structopt(long)]
+        json: bool,
+    },
 }
 
-pub struct TVOptions {
-    pub enabled: bool,
-    pub title: Option<String>,
-    pub season: Option<usize>,
-    pub episode: Option<usize>,
+#[derive(StructOpt, Debug)]
+pub enum ConfigCommand {
+    /// Print the configuration in effect, after merging the configuration files and the other arguments
+    Show {
+        /// The directory whose configuration file is used
+        #[structopt(default_value = ".")]
+        path: PathBuf,
+    },
+}
+
+arg_enum! {
+    #[derive(Debug, Clone, Copy)]
+    pub enum PlanFormat {
+        Json,
+    }
 }
 
-pub fn get_tv_options() -> Result<TVOptions, Box<dyn std::error::Error>> {
+pub fn get_tv_options() -> Result<TVOptions, Error> {
     let enabled = util::confirm("TV Show Mode", false)?;
     if !enabled {
         let title = None;
@@ -96,7 +208,7 @@ pub fn get_tv_options() -> Result<TVOptions, Box<dyn std::error::Error>> {
     let mut title: Option<String> = None;
 
     if using_previous {
-        print!("Use previous title? ({})", previous.as_ref().unwrap().title.as_ref().unwrap());
+        eprint!("Use previous title? ({})", previous.as_ref().unwrap().title.as_ref().unwrap());
         let b = util::confirm("", false)?;
         if b {
             title = previous.as_ref().unwrap().title.clone();
@@ -118,7 +230,7 @@ pub fn get_tv_options() -> Result<TVOptions, Box<dyn std::error::Error>> {
     let episode;
 
     if using_previous {
-        print!("Use previous season? ({})", previous.as_ref().and_then(|x| x.season.as_ref()).unwrap());
+        eprint!("Use previous season? ({})", previous.as_ref().and_then(|x| x.season.as_ref()).unwrap());
         let b = util::confirm("", false)?;
         if b {
             season = previous.as_ref().unwrap().season.clone();
@@ -0,0 +1,88 @@
+use crate::error::Error;
+use crate::sample::sample_frames;
+
+use ffmpeg::frame;
+use serde::Serialize;
+use std::path::Path;
+
+/// The number of frames decoded at each point in the file. idet needs a run of frames to tell repeated fields apart.
+const FRAMES_PER_POINT: usize = 50;
+/// Below this fraction of interlaced frames, the video is progressive.
+const PROGRESSIVE_THRESHOLD: f64 = 0.1;
+/// At or above this fraction of interlaced frames, the video is interlaced throughout.
+const INTERLACED_THRESHOLD: f64 = 0.8;
+/// At or above this fraction of frames with a repeated field, the video is telecined. 3:2 pulldown repeats a field in
+/// two of every five frames.
+const TELECINE_THRESHOLD: f64 = 0.15;
+
+/// How the frames of a video are scanned, as measured from its content rather than from its metadata.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanType {
+    Progressive,
+    /// Every frame holds two fields from different moments.
+    Interlaced,
+    /// Progressive film, with fields repeated to raise the frame rate (such as 3:2 pulldown to 29.97fps).
+    Telecined,
+    /// Both progressive and interlaced sections, as in film with interlaced video edited in.
+    Mixed,
+}
+
+/// The totals idet counts over all of the frames it has seen.
+#[derive(Debug, Clone, Copy, Default)]
+struct Counts {
+    tff: u64,
+    bff: u64,
+    progressive: u64,
+    repeated: u64,
+    neither: u64,
+}
+
+impl Counts {
+    /// Read the totals from the metadata idet attaches to a frame.
+    fn from_frame(frame: &frame::Video) -> Option<Counts> {
+        let metadata = frame.metadata();
+        let get = |key: &str| metadata.get(&format!("lavfi.idet.{}", key))?.parse::<u64>().ok();
+        return Some(Counts {
+            tff: get("multiple.tff")?,
+            bff: get("multiple.bff")?,
+            progressive: get("multiple.progressive")?,
+            repeated: get("repeated.top")? + get("repeated.bottom")?,
+            neither: get("repeated.neither")?,
+        });
+    }
+
+    fn classify(&self) -> Option<ScanType> {
+        let interlaced = self.tff + self.bff;
+        let frames = interlaced + self.progressive;
+        if frames == 0 {
+            return None;
+        }
+        let interlaced_ratio = interlaced as f64 / frames as f64;
+        let repeated_ratio = self.repeated as f64 / (self.repeated + self.neither).max(1) as f64;
+
+        if interlaced_ratio < PROGRESSIVE_THRESHOLD {
+            return Some(ScanType::Progressive);
+        }
+        if repeated_ratio >= TELECINE_THRESHOLD {
+            return Some(ScanType::Telecined);
+        }
+        if interlaced_ratio >= INTERLACED_THRESHOLD {
+            return Some(ScanType::Interlaced);
+        }
+        return Some(ScanType::Mixed);
+    }
+}
+
+/// Classify how a video stream is scanned, by running libav*'s idet filter on frames sampled from across the file.
+/// Returns `None` if idet could not classify any of the frames.
+pub fn detect(path: &Path, stream_index: usize) -> Result<Option<ScanType>, Error> {
+    let mut counts = None;
+    // idet's totals cover every frame so far, so the last frame has the totals for the whole sample
+    sample_frames(path, stream_index, "idet", FRAMES_PER_POINT, |frame| {
+        if let Some(x) = Counts::from_frame(frame) {
+            counts = Some(x);
+        }
+    })?;
+    return Ok(counts.and_then(|x| x.classify()));
+}
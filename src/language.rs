@@ -0,0 +1,156 @@
+//! Normalises the many ways containers tag languages to ISO 639-2/B, the form matroska uses.
+
+/// The language tag of streams whose language is unknown.
+pub const UNDETERMINED: &str = "und";
+
+/// Languages, as their ISO 639-2/B code, ISO 639-1 code (if they have one), ISO 639-2/T code (if it differs from the
+/// bibliographic one), and English names in lower case.
+const LANGUAGES: &[(&str, Option<&str>, Option<&str>, &[&str])] = &[
+    ("alb", Some("sq"), Some("sqi"), &["albanian"]),
+    ("ara", Some("ar"), None, &["arabic"]),
+    ("arm", Some("hy"), Some("hye"), &["armenian"]),
+    ("baq", Some("eu"), Some("eus"), &["basque"]),
+    ("ben", Some("bn"), None, &["bengali", "bangla"]),
+    ("bos", Some("bs"), None, &["bosnian"]),
+    ("bul", Some("bg"), None, &["bulgarian"]),
+    ("bur", Some("my"), Some("mya"), &["burmese"]),
+    ("cat", Some("ca"), None, &["catalan"]),
+    ("chi", Some("zh"), Some("zho"), &["chinese", "mandarin"]),
+    ("cze", Some("cs"), Some("ces"), &["czech"]),
+    ("dan", Some("da"), None, &["danish"]),
+    ("dut", Some("nl"), Some("nld"), &["dutch", "flemish"]),
+    ("eng", Some("en"), None, &["english"]),
+    ("est", Some("et"), None, &["estonian"]),
+    ("fil", None, None, &["filipino"]),
+    ("fin", Some("fi"), None, &["finnish"]),
+    ("fre", Some("fr"), Some("fra"), &["french"]),
+    ("geo", Some("ka"), Some("kat"), &["georgian"]),
+    ("ger", Some("de"), Some("deu"), &["german"]),
+    ("gle", Some("ga"), None, &["irish"]),
+    ("glg", Some("gl"), None, &["galician"]),
+    ("gre", Some("el"), Some("ell"), &["greek"]),
+    ("heb", Some("he"), None, &["hebrew"]),
+    ("hin", Some("hi"), None, &["hindi"]),
+    ("hrv", Some("hr"), None, &["croatian"]),
+    ("hun", Some("hu"), None, &["hungarian"]),
+    ("ice", Some("is"), Some("isl"), &["icelandic"]),
+    ("ind", Some("id"), None, &["indonesian"]),
+    ("ita", Some("it"), None, &["italian"]),
+    ("jpn", Some("ja"), None, &["japanese"]),
+    ("kan", Some("kn"), None, &["kannada"]),
+    ("kaz", Some("kk"), None, &["kazakh"]),
+    ("khm", Some("km"), None, &["khmer", "cambodian"]),
+    ("kor", Some("ko"), None, &["korean"]),
+    ("lat", Some("la"), None, &["latin"]),
+    ("lav", Some("lv"), None, &["latvian"]),
+    ("lit", Some("lt"), None, &["lithuanian"]),
+    ("mac", Some("mk"), Some("mkd"), &["macedonian"]),
+    ("mal", Some("ml"), None, &["malayalam"]),
+    ("mar", Some("mr"), None, &["marathi"]),
+    ("may", Some("ms"), Some("msa"), &["malay"]),
+    ("mon", Some("mn"), None, &["mongolian"]),
+    ("nob", Some("nb"), None, &["norwegian bokmal", "bokmal"]),
+    ("nno", Some("nn"), None, &["norwegian nynorsk", "nynorsk"]),
+    ("nor", Some("no"), None, &["norwegian"]),
+    ("per", Some("fa"), Some("fas"), &["persian", "farsi"]),
+    ("pol", Some("pl"), None, &["polish"]),
+    ("por", Some("pt"), None, &["portuguese"]),
+    ("pan", Some("pa"), None, &["punjabi", "panjabi"]),
+    ("rum", Some("ro"), Some("ron"), &["romanian", "moldavian"]),
+    ("rus", Some("ru"), None, &["russian"]),
+    ("slo", Some("sk"), Some("slk"), &["slovak"]),
+    ("slv", Some("sl"), None, &["slovenian", "slovene"]),
+    ("spa", Some("es"), None, &["spanish", "castilian"]),
+    ("srp", Some("sr"), None, &["serbian"]),
+    ("swa", Some("sw"), None, &["swahili"]),
+    ("swe", Some("sv"), None, &["swedish"]),
+    ("tam", Some("ta"), None, &["tamil"]),
+    ("tel", Some("te"), None, &["telugu"]),
+    ("tgl", Some("tl"), None, &["tagalog"]),
+    ("tha", Some("th"), None, &["thai"]),
+    ("tib", Some("bo"), Some("bod"), &["tibetan"]),
+    ("tur", Some("tr"), None, &["turkish"]),
+    ("ukr", Some("uk"), None, &["ukrainian"]),
+    ("urd", Some("ur"), None, &["urdu"]),
+    ("vie", Some("vi"), None, &["vietnamese"]),
+    ("wel", Some("cy"), Some("cym"), &["welsh"]),
+    ("yid", Some("yi"), None, &["yiddish"]),
+    ("zul", Some("zu"), None, &["zulu"]),
+];
+
+/// Tags that say a stream's language is unknown, or that it has none.
+const UNKNOWN: &[&str] = &["und", "unk", "unknown", "undetermined", "zxx"];
+
+/// The ISO 639-2/B code of a language tag, which may be an ISO 639-1 or 639-2 code, an English name, or a BCP 47
+/// tag such as "en-US". Returns `None` if the tag says the language is unknown. Tags that aren't recognised are
+/// kept as they are, in lower case, so that they can still be matched exactly.
+pub fn normalize(tag: &str) -> Option<String> {
+    let tag = tag.trim().to_lowercase();
+    if tag.is_empty() || UNKNOWN.contains(&tag.as_str()) {
+        return None;
+    }
+
+    let name = tag.as_str();
+    // BCP 47 tags start with the language, followed by the script, region and so on: "zh-Hant-TW"
+    let primary = name.split(|x| x == '-' || x == '_').next().unwrap_or(name);
+    let found = LANGUAGES.iter().find(|(bibliographic, alpha2, terminology, names)| {
+        *bibliographic == primary || *alpha2 == Some(primary) || *terminology == Some(primary) || names.contains(&name)
+    });
+    return match found {
+        Some((bibliographic, ..)) => Some(bibliographic.to_string()),
+        None if primary == UNDETERMINED => None,
+        None => Some(tag),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_codes() {
+        let cases = [
+            ("en", "eng"),
+            ("eng", "eng"),
+            ("de", "ger"),
+            ("ger", "ger"),
+            ("deu", "ger"),
+            ("fr", "fre"),
+            ("fra", "fre"),
+            ("zho", "chi"),
+            (" JPN ", "jpn"),
+        ];
+        for (tag, expected) in cases.iter() {
+            assert_eq!(normalize(tag).as_deref(), Some(*expected), "{}", tag);
+        }
+    }
+
+    #[test]
+    fn normalizes_bcp47_tags() {
+        let cases = [("en-US", "eng"), ("en_GB", "eng"), ("pt-BR", "por"), ("zh-Hant-TW", "chi")];
+        for (tag, expected) in cases.iter() {
+            assert_eq!(normalize(tag).as_deref(), Some(*expected), "{}", tag);
+        }
+    }
+
+    #[test]
+    fn normalizes_names() {
+        let cases = [("English", "eng"), ("french", "fre"), ("Norwegian Bokmal", "nob"), ("Farsi", "per")];
+        for (tag, expected) in cases.iter() {
+            assert_eq!(normalize(tag).as_deref(), Some(*expected), "{}", tag);
+        }
+    }
+
+    #[test]
+    fn unknown_languages_have_no_tag() {
+        for tag in ["", " ", "und", "UND", "zxx", "unknown", "und-US"].iter() {
+            assert_eq!(normalize(tag), None, "{}", tag);
+        }
+    }
+
+    #[test]
+    fn keeps_unrecognised_tags() {
+        assert_eq!(normalize("Klingon").as_deref(), Some("klingon"));
+        assert_eq!(normalize("tlh").as_deref(), Some("tlh"));
+    }
+}
@@ -0,0 +1,130 @@
+//! Decides how to convert video files to matroska, and converts them.
+//!
+//! A file is converted in three steps:
+//!
+//! 1. [`probe`] the file into a [`MediaInfo`], with a list of [`Stream`]s.
+//! 2. Choose the streams to keep with [`frontend::get_stream_mappings`], and decide whether each is copied or
+//!    re-encoded with [`frontend::get_codec_mapping`]. [`create_job`] does both of these steps at once.
+//! 3. Hand the resulting [`Job`] to a [`Backend`], or turn it into a serializable [`Plan`] to review the decisions.
+//!
+//! All of the decisions are controlled by [`Options`]. libav* must be initialised with `ffmpeg_next::init` before
+//! anything else is called.
+
+extern crate ffmpeg_next as ffmpeg;
+
+pub mod backend;
+pub mod crop;
+pub mod error;
+pub mod filtergraph;
+pub mod frontend;
+pub mod interlace;
+pub mod language;
+pub mod options;
+pub mod plan;
+pub mod policy;
+pub mod preset;
+pub mod progress;
+pub mod report;
+mod sample;
+pub mod scan;
+
+pub use backend::{Backend, Job};
+pub use error::Error;
+pub use frontend::{MediaInfo, Stream, StreamMappings};
+pub use options::Options;
+use options::Tonemap;
+pub use plan::Plan;
+pub use report::Report;
+
+use log::warn;
+use std::path::{Path, PathBuf};
+
+fn open(path: &Path) -> Result<ffmpeg::format::context::Input, Error> {
+    return ffmpeg::format::input(&path).map_err(|source| Error::Probe {
+        path: path.to_path_buf(),
+        source,
+    });
+}
+
+/// Read the container of a file, and its video, audio and subtitle streams. Any other streams are ignored.
+pub fn probe<P: AsRef<Path>>(path: P) -> Result<MediaInfo, Error> {
+    let file = open(path.as_ref())?;
+    return Ok(MediaInfo::new(&file));
+}
+
+/// Probe a file and decide how to convert it to `output_path`.
+pub fn create_job<P: AsRef<Path>>(input_path: P, output_path: PathBuf, options: &Options) -> Result<Job, Error> {
+    let input_path = input_path.as_ref();
+    let info = MediaInfo::new(&open(input_path)?);
+    return create_job_from_info(input_path, output_path, &info, options);
+}
+
+/// Decide how to convert a file that has already been probed into `info` to `output_path`. The file is only read again
+/// if `options` ask for its crop or interlacing to be detected.
+pub fn create_job_from_info(input_path: &Path, output_path: PathBuf, info: &MediaInfo, options: &Options) -> Result<Job, Error> {
+    let mut stream_mappings = frontend::get_stream_mappings(&info.streams, options);
+    let crop = options.crop.as_ref().map(|x| x.parse()).transpose()?;
+    let codec_mappings = frontend::get_codec_mapping(&stream_mappings, crop, options);
+
+    // Only re-encoded video can be cropped, so don't spend time detecting a crop for anything else. The video is
+    // re-encoded whatever crop is detected, so it doesn't change the codec mapping.
+    let reencoded_video = stream_mappings
+        .video
+        .iter()
+        .find(|x| codec_mappings.get(&x.index()).is_some_and(Option::is_some));
+    let crop = match (crop, reencoded_video) {
+        (Some(x), _) => Some(x),
+        (None, Some(video)) if options.auto_crop => match crop::detect(input_path, video.index()) {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("Failed to detect the crop of '{}': {}", input_path.display(), e);
+                None
+            }
+        },
+        (None, _) => None,
+    };
+
+    if options.detect_interlace && !options.no_deinterlace {
+        let reencoded = |x: &Stream| codec_mappings.get(&x.index()).is_some_and(Option::is_some);
+        for stream in stream_mappings.video.iter_mut().filter(|x| reencoded(x)) {
+            if let Stream::Video(video) = stream {
+                match interlace::detect(input_path, video.index) {
+                    Ok(x) => video.scan = x,
+                    Err(e) => warn!("Failed to detect the interlacing of '{}': {}", input_path.display(), e),
+                }
+            }
+        }
+    }
+
+    return Ok(Job {
+        input_path: input_path.to_path_buf(),
+        output_path,
+        mappings: stream_mappings,
+        codecs: codec_mappings,
+        duration: info.duration,
+        tonemap: options.tonemap == Some(Tonemap::Replace),
+        crop,
+    });
+}
+
+/// Decide how to convert the file of `job` to a tone-mapped SDR rendition, written next to `job`'s output. Returns
+/// `None` if the file has no HDR video.
+pub fn create_sdr_job(job: &Job, options: &Options) -> Option<Job> {
+    if !job.has_hdr_video() {
+        return None;
+    }
+
+    let options = Options {
+        tonemap: Some(Tonemap::Replace),
+        ..options.clone()
+    };
+    let stem = job.output_path.file_stem()?.to_string_lossy();
+    let output_path = job.output_path.with_file_name(format!("{} - SDR.mkv", stem));
+
+    return Some(Job {
+        output_path,
+        codecs: frontend::get_codec_mapping(&job.mappings, job.crop, &options),
+        tonemap: true,
+        ..job.clone()
+    });
+}
@@ -0,0 +1,93 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::cell::RefCell;
+
+thread_local! {
+    static BUFFER: RefCell<Option<Vec<BufferedRecord>>> = const { RefCell::new(None) };
+}
+
+struct BufferedRecord {
+    level: Level,
+    target: String,
+    message: String,
+}
+
+/// Wraps pretty_env_logger, allowing the records logged by a thread to be held back and emitted later as one block.
+///
+/// This stops the logs of jobs running in parallel from being interleaved.
+struct JobLogger {
+    inner: Box<dyn Log>,
+}
+
+impl Log for JobLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.inner.enabled(record.metadata()) {
+            return;
+        }
+
+        let buffered = BUFFER.with(|buffer| match buffer.borrow_mut().as_mut() {
+            Some(buffer) => {
+                buffer.push(BufferedRecord {
+                    level: record.level(),
+                    target: record.target().to_string(),
+                    message: record.args().to_string(),
+                });
+                true
+            }
+            None => false,
+        });
+
+        if !buffered {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// The records logged while running a job.
+pub struct JobLog {
+    records: Vec<BufferedRecord>,
+}
+
+impl JobLog {
+    /// Emit all of the held back records.
+    pub fn emit(self) {
+        let logger = log::logger();
+        for record in self.records {
+            logger.log(
+                &Record::builder()
+                    .level(record.level)
+                    .target(&record.target)
+                    .args(format_args!("{}", record.message))
+                    .build(),
+            );
+        }
+    }
+}
+
+/// Initialise logging, configured by `RUST_LOG` in the same way as `pretty_env_logger::init`.
+pub fn init() {
+    let mut builder = pretty_env_logger::formatted_builder();
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+    let inner = builder.build();
+    let max_level: LevelFilter = inner.filter();
+
+    log::set_boxed_logger(Box::new(JobLogger { inner: Box::new(inner) })).expect("Logger already initialised");
+    log::set_max_level(max_level);
+}
+
+/// Run `f`, holding back everything it logs on this thread until the returned `JobLog` is emitted.
+pub fn capture<R, F: FnOnce() -> R>(f: F) -> (R, JobLog) {
+    BUFFER.with(|buffer| *buffer.borrow_mut() = Some(Vec::new()));
+    let result = f();
+    let records = BUFFER.with(|buffer| buffer.borrow_mut().take()).unwrap_or_default();
+    return (result, JobLog { records });
+}
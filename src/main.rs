@@ -1,40 +1,70 @@
-#[macro_use]
-extern crate lazy_static;
 extern crate ffmpeg_next as ffmpeg;
 
-mod backend;
-mod frontend;
+mod config;
 mod interface;
+mod logging;
+mod scheduler;
 mod util;
 
-use ffmpeg::codec;
-use frontend::StreamMappings;
-use interface::Opt;
-use itertools::sorted;
+use interface::{Command, ConfigCommand, PlanFormat};
 use log::{debug, error, info, warn};
-use regex::Regex;
-use std::collections::HashMap;
+use scheduler::JobKind;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
+use videoconverter::backend;
+use videoconverter::backend::cli::CliBackend;
+use videoconverter::backend::native::NativeBackend;
+use videoconverter::backend::recording::RecordingBackend;
+use videoconverter::backend::Backend;
+use videoconverter::options::Tonemap;
+use videoconverter::plan::StreamType;
+use videoconverter::policy::Target;
+use videoconverter::progress::BatchProgress;
+use videoconverter::scan;
+use videoconverter::Plan;
+use videoconverter::Report;
+use videoconverter::StreamMappings;
+
+/// The exit code when some, but not all, of the files failed to convert.
+const EXIT_SOME_FAILED: i32 = 2;
+/// The exit code when every file failed to convert.
+const EXIT_ALL_FAILED: i32 = 3;
+
+/// A file that could not be converted, and why.
+struct Failure {
+    input_path: PathBuf,
+    reason: String,
+}
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     ffmpeg::init()?;
-    pretty_env_logger::init();
-
-    lazy_static! {
-        static ref EXEMPT_EXTENSION_REGEX: Regex = Regex::new(r"r\d+").unwrap();
-    }
+    logging::init();
 
     let args = interface::Opt::from_args();
 
-    validate_args(&args);
-
-    debug!("{:?}", args);
-
     // Squelch libav* errors
     unsafe {
         ffmpeg::ffi::av_log_set_level(ffmpeg::ffi::AV_LOG_FATAL);
     }
 
+    match args.command.as_ref() {
+        Some(Command::Config(ConfigCommand::Show { path })) => {
+            let config = config::resolve(&args, path)?;
+            print!("{}", toml::to_string_pretty(&config.to_config())?);
+            return Ok(());
+        }
+        Some(Command::Probe { paths, json }) => return probe(&args, paths, *json),
+        None => {}
+    }
+
+    let config::Resolved { options, settings, .. } = config::resolve(&args, &args.path)?;
+    options.validate()?;
+    // Planning never converts anything
+    let simulate = args.simulate || args.plan.is_some();
+
+    debug!("{:?}", args);
+
     let mut tv_options = interface::get_tv_options()?;
 
     if tv_options.enabled {
@@ -51,120 +81,307 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         tv_options.enabled, tv_options.title, tv_options.season, tv_options.episode
     );
 
-    let entries = sorted(
-        std::fs::read_dir(&args.path)?
-            .map(|entry| entry.unwrap().path())
-            .filter(|path| !path.is_dir()) // Remove directories
-            .filter(|path| {
-                // Remove files that start with '.'
-                let filename = path.file_name().and_then(|x| x.to_str()).unwrap();
-                filename.chars().nth(0).unwrap() != '.'
-            })
-            .filter(|path| {
-                // Remove files with extensions that are exempt
-                let file_extension = match path.extension().and_then(|x| x.to_str()) {
-                    Some(x) => x,
-                    None => {
-                        return false;
-                    }
-                };
-                let exempt_file_extensions = ["gif", "jpg", "md", "nfo", "png", "py", "rar", "sfv", "srr", "txt"];
-                return !(exempt_file_extensions.contains(&file_extension) || EXEMPT_EXTENSION_REGEX.is_match(file_extension));
-            }),
-    );
+    let output_root = if tv_options.enabled {
+        args.path.join(format!("Season {:02}", tv_options.season.unwrap()))
+    } else {
+        args.path.join("newfiles")
+    };
 
-    // prepare directory
-    {
-        let dir_to_make = if tv_options.enabled {
-            args.path.join(format!("Season {:02}", tv_options.season.unwrap()))
-        } else {
-            args.path.join("newfiles")
-        };
-        let dir_as_str: &str = dir_to_make.as_os_str().to_str().expect("Path contained invalid unicode.");
+    let filter = scan::Filter::new(&settings.include, &settings.exclude)?;
+    let scan::Entries { media: entries, failed } = scan::get_entries(&args.path, settings.recursive, &output_root, &filter)?;
 
-        if dir_to_make.is_dir() {
-            info!("Directory '{}' already exists.", dir_as_str);
-        } else {
-            if args.simulate {
-                info!("Simulate mode: not creating directory '{}'", dir_as_str);
-            } else {
-                std::fs::create_dir(&dir_to_make)?;
-                info!("Created directory '{}'.", dir_as_str);
-            }
+    prepare_directory(&output_root, simulate)?;
+
+    let real_backend: Box<dyn Backend> = if settings.native {
+        Box::new(NativeBackend)
+    } else {
+        Box::new(CliBackend {
+            capture_output: settings.jobs > 1,
+        })
+    };
+    let recording_backend = RecordingBackend::new(real_backend.as_ref());
+    let selected_backend: &dyn Backend = if simulate { &recording_backend } else { real_backend.as_ref() };
+
+    // Files to convert, including any SDR renditions and any files that could not be probed
+    let mut entry_count = entries.len() + failed.len();
+    let mut jobs = Vec::new();
+    let mut failures = Vec::new();
+    for (input_path, e) in failed {
+        if settings.fail_fast {
+            return Err(e.into());
         }
+        error!("{}", e);
+        failures.push(Failure {
+            input_path,
+            reason: e.to_string(),
+        });
     }
+    let mut prepared_dirs = HashSet::new();
 
     for input_path in entries {
-        let output_filename = backend::generate_output_filename(&input_path, &tv_options);
-        let output_path = if tv_options.enabled {
-            input_path
-                .parent()
-                .expect("Somehow the input_path was root")
-                .join(format!("Season {:02}", tv_options.season.unwrap()))
-        } else {
-            input_path.parent().expect("Somehow the input_path was root").join("newfiles")
+        let output_filename = backend::generate_output_filename(&input_path, &tv_options)?;
+
+        // Mirror the layout of subdirectories into the output directory
+        let relative_dir = input_path
+            .parent()
+            .and_then(|x| x.strip_prefix(&args.path).ok())
+            .expect("Input path was not inside the target directory");
+        let output_dir = output_root.join(relative_dir);
+        if !relative_dir.as_os_str().is_empty() && prepared_dirs.insert(output_dir.clone()) {
+            prepare_directory(&output_dir, simulate)?;
         }
-        .join(output_filename);
+        let output_path = output_dir.join(output_filename);
 
         if let Some(ref mut e) = tv_options.episode {
             *e += 1;
         }
 
-        info!(
-            "Mapping '{}' --> '{}'",
-            input_path.as_os_str().to_str().expect("Path contained invalid unicode."),
-            output_path.as_os_str().to_str().expect("Path contained invalid unicode.")
-        );
+        info!("Mapping '{}' --> '{}'", input_path.display(), output_path.display());
 
-        let file = ffmpeg::format::input(&input_path)?;
+        match videoconverter::create_job(&input_path, output_path, &options) {
+            Ok(job) => {
+                log_mappings(&job.mappings, &job.codecs);
+                let sdr_job = match options.tonemap {
+                    Some(Tonemap::Alongside) => videoconverter::create_sdr_job(&job, &options),
+                    _ => None,
+                };
+                jobs.push(job);
+                if let Some(sdr_job) = sdr_job {
+                    info!("Mapping '{}' --> '{}' (SDR)", sdr_job.input_path.display(), sdr_job.output_path.display());
+                    log_mappings(&sdr_job.mappings, &sdr_job.codecs);
+                    jobs.push(sdr_job);
+                    entry_count += 1;
+                }
+            }
+            Err(e) if settings.fail_fast => return Err(e.into()),
+            Err(e) => {
+                error!("{}", e);
+                failures.push(Failure {
+                    input_path,
+                    reason: e.to_string(),
+                });
+            }
+        }
+    }
 
-        let parsed = frontend::parse_stream_metadata(&file);
-        let stream_mappings = frontend::get_stream_mappings(&parsed, &args);
-        let codec_mappings = frontend::get_codec_mapping(&stream_mappings, &args);
+    if let Some(format) = args.plan {
+        let plans: Vec<Plan> = jobs.iter().map(|job| Plan::new(job, &options)).collect();
+        match format {
+            PlanFormat::Json => {
+                serde_json::to_writer_pretty(std::io::stdout().lock(), &plans)?;
+                println!();
+            }
+        }
+        exit_on_failure(&failures, entry_count);
+        return Ok(());
+    }
+
+    let total_duration = jobs.iter().filter_map(|job| job.duration).sum();
+    let progress = BatchProgress::new(total_duration, args.simulate);
+
+    let limits = scheduler::Limits {
+        total: settings.jobs,
+        remux: settings.remux_jobs.unwrap_or(settings.jobs),
+        encode: settings.encode_jobs,
+    };
+    let input_paths: Vec<PathBuf> = jobs.iter().map(|job| job.input_path.clone()).collect();
+    let jobs = jobs
+        .into_iter()
+        .map(|job| (if job.is_remux() { JobKind::Remux } else { JobKind::Encode }, job))
+        .collect();
 
-        log_mappings(&stream_mappings, &codec_mappings);
+    let results = scheduler::run(jobs, limits, settings.fail_fast, |job| {
+        let name = job.input_path.file_name().map(|x| x.to_string_lossy().into_owned()).unwrap_or_default();
+        let file_progress = progress.start_file(&name, job.duration);
+        let result = backend::run(selected_backend, job, &options, &file_progress);
+        file_progress.finish();
+        result.map_err(|e| e.to_string())
+    });
 
-        let mut command = backend::generate_ffmpeg_command(input_path, output_path, &stream_mappings, &codec_mappings, &args)?;
+    progress.finish();
 
-        info!("{:?}", command);
-        if !args.simulate {
-            command.status()?;
+    let mut skipped = 0;
+    for (input_path, result) in input_paths.into_iter().zip(results) {
+        match result {
+            Some(Ok(())) => {}
+            Some(Err(reason)) => failures.push(Failure { input_path, reason }),
+            None => skipped += 1,
         }
     }
+    failures.sort_by(|a, b| a.input_path.cmp(&b.input_path));
+
+    if args.simulate {
+        info!("Simulate mode: {} file(s) would have been converted", recording_backend.jobs().len());
+    } else {
+        print_summary(entry_count, &failures, skipped);
+    }
+
+    exit_on_failure(&failures, entry_count);
 
     return Ok(());
 }
 
-fn log_mappings(mappings: &StreamMappings, codecs: &HashMap<usize, Option<codec::Id>>) {
-    for stream in mappings.iter() {
-        let index = stream.index();
-        let codec = codecs.get(&index).unwrap();
-        let oldcodec = stream.codec();
-        let newcodec = match codec {
-            None => &oldcodec,
-            Some(x) => x,
-        };
-        info!(
-            "Mapping stream {}: {:?} -> {:?}{}",
-            index,
-            oldcodec,
-            newcodec,
-            if codec.is_none() { " (copy)" } else { "" }
-        );
+/// Print what is known about each file, and what converting it would do, as a table or as JSON.
+fn probe(args: &interface::Opt, paths: &[PathBuf], json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let mut reports = Vec::new();
+    let mut failures = Vec::new();
+
+    for path in paths {
+        // Use the configuration of the file's directory, as converting that directory would
+        let dir = path.parent().filter(|x| !x.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+        let config::Resolved { options, .. } = config::resolve(args, dir)?;
+        options.validate()?;
+
+        match videoconverter::probe(path) {
+            Ok(info) => reports.push(Report::new(path, &info, &options)),
+            Err(e) => {
+                error!("{}", e);
+                failures.push(Failure {
+                    input_path: path.clone(),
+                    reason: e.to_string(),
+                });
+            }
+        }
+    }
+
+    if json {
+        serde_json::to_writer_pretty(std::io::stdout().lock(), &reports)?;
+        println!();
+    } else {
+        for report in reports.iter() {
+            print_report(report);
+        }
+    }
+
+    exit_on_failure(&failures, paths.len());
+    return Ok(());
+}
+
+/// Print a file's container, then a table of its streams.
+fn print_report(report: &Report) {
+    let mut summary = vec![report.format_long_name.clone()];
+    if let Some(duration) = report.duration {
+        let seconds = duration as u64;
+        summary.push(format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60));
+    }
+    if let Some(bit_rate) = report.bit_rate {
+        summary.push(format!("{}kb/s", bit_rate / 1000));
+    }
+    if let Some(title) = report.title.as_ref() {
+        summary.push(format!("\"{}\"", title));
+    }
+    println!("{}", report.path.display());
+    println!("  {}", summary.join(", "));
+    println!();
+
+    let header = ["#", "Type", "Codec", "Language", "Details", "Flags", "Title", "Decision"].map(str::to_string);
+    let rows: Vec<[String; 8]> = report
+        .streams
+        .iter()
+        .map(|x| {
+            let stream_type = match x.stream_type {
+                StreamType::Video => "video",
+                StreamType::Audio => "audio",
+                StreamType::Subtitle => "subtitle",
+            };
+            let codec = match x.profile.as_ref() {
+                Some(profile) => format!("{} ({})", x.codec, profile),
+                None => x.codec.clone(),
+            };
+            [
+                x.index.to_string(),
+                stream_type.to_string(),
+                codec,
+                x.language.clone().unwrap_or_default(),
+                x.details(),
+                x.disposition.join(","),
+                x.title.clone().unwrap_or_default(),
+                x.decision(),
+            ]
+        })
+        .collect();
+
+    let widths: Vec<usize> = (0..header.len())
+        .map(|i| std::iter::once(&header).chain(rows.iter()).map(|x| x[i].chars().count()).max().unwrap())
+        .collect();
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(x, width)| format!("{:width$}", x, width = width))
+            .collect();
+        println!("  {}", cells.join("  ").trim_end());
     }
+    println!();
 }
 
-fn validate_args(args: &Opt) {
-    if args.gpu && args.no_hwaccel {
-        error!("The arguments gpu and no_hwaccel are incompatible");
-        panic!();
+/// Exit with a code saying whether some or all of the files failed, if any did.
+fn exit_on_failure(failures: &[Failure], total: usize) {
+    if !failures.is_empty() {
+        std::process::exit(if failures.len() == total { EXIT_ALL_FAILED } else { EXIT_SOME_FAILED });
     }
-    if args.gpu && args.tune.is_some() {
-        error!("The arguments gpu and tune are incompatible");
-        panic!();
+}
+
+/// Print how many files were converted, and a table of the files that failed.
+fn print_summary(total: usize, failures: &[Failure], skipped: usize) {
+    let converted = total - failures.len() - skipped;
+    eprintln!();
+    eprintln!(
+        "Converted {} of {} file(s), {} failed, {} not attempted",
+        converted,
+        total,
+        failures.len(),
+        skipped
+    );
+
+    if failures.is_empty() {
+        return;
+    }
+
+    let names: Vec<String> = failures.iter().map(|x| x.input_path.display().to_string()).collect();
+    let width = names
+        .iter()
+        .map(|x| x.chars().count())
+        .chain(std::iter::once("File".len()))
+        .max()
+        .unwrap();
+
+    eprintln!();
+    eprintln!("{:width$}  {}", "File", "Reason", width = width);
+    for (name, failure) in names.iter().zip(failures) {
+        eprintln!("{:width$}  {}", name, failure.reason, width = width);
     }
-    if args.force_deinterlace && args.no_deinterlace {
-        error!("The arguments force_deinterlace and no_deinterlace are incompatible");
-        panic!();
+}
+
+fn prepare_directory(dir: &Path, simulate: bool) -> std::io::Result<()> {
+    let dir_as_str = dir.display();
+
+    if dir.is_dir() {
+        info!("Directory '{}' already exists.", dir_as_str);
+    } else {
+        if simulate {
+            info!("Simulate mode: not creating directory '{}'", dir_as_str);
+        } else {
+            std::fs::create_dir_all(dir)?;
+            info!("Created directory '{}'.", dir_as_str);
+        }
+    }
+    return Ok(());
+}
+
+fn log_mappings(mappings: &StreamMappings, codecs: &HashMap<usize, Option<Target>>) {
+    for stream in mappings.iter() {
+        let index = stream.index();
+        let oldcodec = stream.codec().name();
+        match codecs.get(&index).unwrap() {
+            None => info!("Mapping stream {}: {} -> {} (copy)", index, oldcodec, oldcodec),
+            Some(target) => info!(
+                "Mapping stream {}: {} -> {} ({})",
+                index,
+                oldcodec,
+                target.codec,
+                target.encoder().unwrap_or("no encoder")
+            ),
+        }
     }
 }
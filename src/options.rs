@@ -0,0 +1,374 @@
+use crate::crop::Crop;
+use crate::error::Error;
+use crate::policy::{CodecPolicy, Target};
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Everything that affects how a file is converted.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Keep all streams, regardless of language metadata.
+    pub all_streams: bool,
+    /// Audio streams in these languages are kept, if there are any, ordered by preference. "und" matches streams
+    /// whose language is unknown.
+    pub audio_langs: Vec<String>,
+    /// Subtitle streams in these languages are kept, in the same way as audio.
+    pub sub_langs: Vec<String>,
+    /// The CRF (or, with `gpu`, the QP) to encode video with, on libx264's scale. It is converted to the equivalent
+    /// for other encoders.
+    pub crf: u8,
+    /// A crop filter, of the format 'crop=width:height:x:y'.
+    pub crop: Option<String>,
+    /// Detect the crop of each file that has its video re-encoded.
+    pub auto_crop: bool,
+    pub force_deinterlace: bool,
+    pub no_deinterlace: bool,
+    /// Analyse the frames of each file that has its video re-encoded to choose how to deinterlace it, rather than
+    /// trusting its field order.
+    pub detect_interlace: bool,
+    /// Denoise re-encoded video, with this strength.
+    pub denoise: Option<Denoise>,
+    /// Video larger than this is scaled down to fit, keeping its aspect ratio, and so always re-encoded.
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+    /// The algorithm video is scaled with.
+    pub scaler: Scaler,
+    /// Re-encode video even if its codec could be copied.
+    pub force_reencode: bool,
+    /// Encode video with nvenc, producing HEVC.
+    pub gpu: bool,
+    /// Encode video with this encoder, in place of the codec policy's video rules.
+    pub video_codec: Option<VideoCodec>,
+    /// Disable hardware-accelerated decoding.
+    pub no_hwaccel: bool,
+    /// Mapped to the nearest equivalent for encoders other than libx264, if there is one.
+    pub tune: Option<Libx264Tune>,
+    /// Whether, and how, HDR video is tone-mapped to SDR.
+    pub tonemap: Option<Tonemap>,
+    /// Passed to ffmpeg, which fails to mux some files with a small queue.
+    pub max_muxing_queue_size: u32,
+    /// Decides which streams are copied, and what the rest are encoded to.
+    pub policy: CodecPolicy,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            all_streams: false,
+            audio_langs: vec!["eng".to_string()],
+            sub_langs: vec!["eng".to_string()],
+            crf: 20,
+            crop: None,
+            auto_crop: false,
+            force_deinterlace: false,
+            no_deinterlace: false,
+            detect_interlace: false,
+            denoise: None,
+            max_width: None,
+            max_height: None,
+            scaler: Scaler::Bicubic,
+            force_reencode: false,
+            gpu: false,
+            video_codec: None,
+            no_hwaccel: false,
+            tune: None,
+            tonemap: None,
+            max_muxing_queue_size: 16384,
+            policy: CodecPolicy::default(),
+        }
+    }
+}
+
+impl Options {
+    /// Check that no contradictory options are set.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.gpu && self.no_hwaccel {
+            return Err(Error::InvalidArguments("The arguments gpu and no_hwaccel are incompatible".to_string()));
+        }
+        if self.gpu && self.video_codec.is_some() {
+            return Err(Error::InvalidArguments("The arguments gpu and video_codec are incompatible".to_string()));
+        }
+        if self.gpu && self.tune.is_some() {
+            return Err(Error::InvalidArguments("The arguments gpu and tune are incompatible".to_string()));
+        }
+        if self.crop.is_some() && self.auto_crop {
+            return Err(Error::InvalidArguments("The arguments crop and auto_crop are incompatible".to_string()));
+        }
+        if let Some(crop) = self.crop.as_ref() {
+            crop.parse::<Crop>()?;
+        }
+        if self.max_width.is_some_and(|x| x < 2) || self.max_height.is_some_and(|x| x < 2) {
+            return Err(Error::InvalidArguments("The maximum width and height must be at least 2".to_string()));
+        }
+        if self.force_deinterlace && self.no_deinterlace {
+            return Err(Error::InvalidArguments(
+                "The arguments force_deinterlace and no_deinterlace are incompatible".to_string(),
+            ));
+        }
+        self.policy.validate()?;
+        return Ok(());
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Libx264Tune {
+    Film,
+    Animation,
+    Grain,
+    Stillimage,
+    Psnr,
+    Ssim,
+    Fastdecode,
+    Zerolatency,
+}
+
+impl Libx264Tune {
+    pub const VARIANTS: &'static [&'static str] = &["film", "animation", "grain", "stillimage", "psnr", "ssim", "fastdecode", "zerolatency"];
+
+    /// The name libx264 knows this tune by.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Libx264Tune::Film => "film",
+            Libx264Tune::Animation => "animation",
+            Libx264Tune::Grain => "grain",
+            Libx264Tune::Stillimage => "stillimage",
+            Libx264Tune::Psnr => "psnr",
+            Libx264Tune::Ssim => "ssim",
+            Libx264Tune::Fastdecode => "fastdecode",
+            Libx264Tune::Zerolatency => "zerolatency",
+        }
+    }
+}
+
+impl fmt::Display for Libx264Tune {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Libx264Tune {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "film" => Ok(Libx264Tune::Film),
+            "animation" => Ok(Libx264Tune::Animation),
+            "grain" => Ok(Libx264Tune::Grain),
+            "stillimage" => Ok(Libx264Tune::Stillimage),
+            "psnr" => Ok(Libx264Tune::Psnr),
+            "ssim" => Ok(Libx264Tune::Ssim),
+            "fastdecode" => Ok(Libx264Tune::Fastdecode),
+            "zerolatency" => Ok(Libx264Tune::Zerolatency),
+            _ => Err(Error::InvalidArguments(format!("Unknown libx264 tune '{}'", s))),
+        }
+    }
+}
+
+/// The software video encoders that can be chosen in place of the codec policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VideoCodec {
+    Libx264,
+    Libx265,
+    Libsvtav1,
+    LibaomAv1,
+}
+
+impl VideoCodec {
+    pub const VARIANTS: &'static [&'static str] = &["libx264", "libx265", "libsvtav1", "libaom-av1"];
+
+    /// The name of the encoder.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VideoCodec::Libx264 => "libx264",
+            VideoCodec::Libx265 => "libx265",
+            VideoCodec::Libsvtav1 => "libsvtav1",
+            VideoCodec::LibaomAv1 => "libaom-av1",
+        }
+    }
+
+    /// What video is encoded to with this encoder. The CRF and tune are added from the options.
+    pub fn target(&self) -> Target {
+        let (codec, options): (&str, &[(&str, &str)]) = match self {
+            VideoCodec::Libx264 => ("h264", &[("profile", "high"), ("rc-lookahead", "250"), ("preset", "slow")]),
+            VideoCodec::Libx265 => ("hevc", &[("preset", "slow")]),
+            // SVT-AV1's presets are numbered, from 0 (slowest) to 13
+            VideoCodec::Libsvtav1 => ("av1", &[("preset", "6")]),
+            VideoCodec::LibaomAv1 => ("av1", &[("cpu-used", "4"), ("row-mt", "1")]),
+        };
+        let options: BTreeMap<String, String> = options.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+
+        return Target {
+            codec: codec.to_string(),
+            encoder: Some(self.as_str().to_string()),
+            options,
+        };
+    }
+}
+
+impl fmt::Display for VideoCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for VideoCodec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "libx264" => Ok(VideoCodec::Libx264),
+            "libx265" => Ok(VideoCodec::Libx265),
+            "libsvtav1" => Ok(VideoCodec::Libsvtav1),
+            "libaom-av1" => Ok(VideoCodec::LibaomAv1),
+            _ => Err(Error::InvalidArguments(format!("Unknown video codec '{}'", s))),
+        }
+    }
+}
+
+/// How HDR video is tone-mapped to SDR (BT.709), for devices that can't play HDR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tonemap {
+    /// The SDR rendition is the only output.
+    Replace,
+    /// The SDR rendition is written to a second file, next to the HDR one.
+    Alongside,
+}
+
+impl Tonemap {
+    pub const VARIANTS: &'static [&'static str] = &["replace", "alongside"];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Tonemap::Replace => "replace",
+            Tonemap::Alongside => "alongside",
+        }
+    }
+}
+
+impl fmt::Display for Tonemap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Tonemap {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "replace" => Ok(Tonemap::Replace),
+            "alongside" => Ok(Tonemap::Alongside),
+            _ => Err(Error::InvalidArguments(format!("Unknown tonemap mode '{}'", s))),
+        }
+    }
+}
+
+/// How strongly video is denoised, with hqdn3d.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Denoise {
+    Light,
+    Medium,
+    Strong,
+}
+
+impl Denoise {
+    pub const VARIANTS: &'static [&'static str] = &["light", "medium", "strong"];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Denoise::Light => "light",
+            Denoise::Medium => "medium",
+            Denoise::Strong => "strong",
+        }
+    }
+
+    /// The spatial and temporal strengths, for luma and chroma, passed to hqdn3d. Medium is hqdn3d's default.
+    pub fn hqdn3d_params(&self) -> &'static str {
+        match self {
+            Denoise::Light => "2:1.5:3:2.25",
+            Denoise::Medium => "4:3:6:4.5",
+            Denoise::Strong => "8:6:12:9",
+        }
+    }
+}
+
+impl fmt::Display for Denoise {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Denoise {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "light" => Ok(Denoise::Light),
+            "medium" => Ok(Denoise::Medium),
+            "strong" => Ok(Denoise::Strong),
+            _ => Err(Error::InvalidArguments(format!("Unknown denoise strength '{}'", s))),
+        }
+    }
+}
+
+/// The algorithms libav* can scale video with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scaler {
+    Bilinear,
+    Bicubic,
+    Lanczos,
+    Spline,
+    Area,
+}
+
+impl Scaler {
+    pub const VARIANTS: &'static [&'static str] = &["bilinear", "bicubic", "lanczos", "spline", "area"];
+
+    /// The name libav* gives the algorithm, as passed to the scale filter's `flags`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scaler::Bilinear => "bilinear",
+            Scaler::Bicubic => "bicubic",
+            Scaler::Lanczos => "lanczos",
+            Scaler::Spline => "spline",
+            Scaler::Area => "area",
+        }
+    }
+}
+
+impl fmt::Display for Scaler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scaler {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "bilinear" => Ok(Scaler::Bilinear),
+            "bicubic" => Ok(Scaler::Bicubic),
+            "lanczos" => Ok(Scaler::Lanczos),
+            "spline" => Ok(Scaler::Spline),
+            "area" => Ok(Scaler::Area),
+            _ => Err(Error::InvalidArguments(format!("Unknown scaler '{}'", s))),
+        }
+    }
+}
+
+/// Settings for TV show mode, where output files are renamed to `<title> - sXXeYY.mkv`.
+pub struct TVOptions {
+    pub enabled: bool,
+    pub title: Option<String>,
+    pub season: Option<usize>,
+    pub episode: Option<usize>,
+}
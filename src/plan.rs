@@ -0,0 +1,105 @@
+use crate::backend;
+use crate::backend::Job;
+use crate::filtergraph::Device;
+use crate::frontend::{Disposition, Stream};
+use crate::interlace::ScanType;
+use crate::options::Options;
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// The decisions made about a file, in a form that can be serialized and reviewed before anything is converted.
+#[derive(Debug, Clone, Serialize)]
+pub struct Plan {
+    pub input_path: PathBuf,
+    pub output_path: PathBuf,
+    /// The crop filter applied to re-encoded video, either given or detected.
+    pub crop: Option<String>,
+    pub streams: Vec<StreamPlan>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamType {
+    Video,
+    Audio,
+    Subtitle,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Copy,
+    Encode,
+}
+
+/// What will happen to a single stream that is kept.
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamPlan {
+    /// The index of the stream in the input file.
+    pub index: usize,
+    #[serde(rename = "type")]
+    pub stream_type: StreamType,
+    pub language: Option<String>,
+    pub source_codec: String,
+    pub action: Action,
+    /// The codec the stream will be encoded to, if it is not copied.
+    pub target_codec: Option<String>,
+    /// The encoder used to produce `target_codec`.
+    pub encoder: Option<String>,
+    pub encoder_options: BTreeMap<String, String>,
+    /// The filters applied to the stream before it is encoded.
+    pub filters: Vec<String>,
+    /// How the video is scanned, if its frames were analysed.
+    pub scan: Option<ScanType>,
+    /// The flags the stream is written with, such as "default" or "forced".
+    pub disposition: Vec<&'static str>,
+}
+
+impl Plan {
+    pub fn new(job: &Job, args: &Options) -> Plan {
+        let dispositions = job.mappings.output_dispositions();
+        let streams = job
+            .mappings
+            .iter()
+            .map(|stream| {
+                let target = job.codecs.get(&stream.index()).cloned().flatten();
+                let (stream_type, language, filters) = match stream {
+                    Stream::Video(x) if target.is_some() => (
+                        StreamType::Video,
+                        None,
+                        backend::video_filters(job, x, args).steps(Device::for_options(args)),
+                    ),
+                    Stream::Video(_) => (StreamType::Video, None, Vec::new()),
+                    Stream::Audio(x) => (StreamType::Audio, x.lang.clone(), Vec::new()),
+                    Stream::Subtitle(x) => (StreamType::Subtitle, x.lang.clone(), Vec::new()),
+                };
+
+                StreamPlan {
+                    index: stream.index(),
+                    stream_type,
+                    language,
+                    source_codec: stream.codec().name().to_string(),
+                    action: if target.is_some() { Action::Encode } else { Action::Copy },
+                    target_codec: target.as_ref().map(|x| x.codec.clone()),
+                    encoder: target.as_ref().and_then(|x| x.encoder().ok()).map(str::to_string),
+                    encoder_options: target
+                        .as_ref()
+                        .and_then(|x| backend::encoder_options(x, stream.as_video(), job.tonemap, args).ok())
+                        .unwrap_or_default(),
+                    filters,
+                    scan: stream.as_video().and_then(|x| x.scan),
+                    disposition: dispositions.get(&stream.index()).map(Disposition::names).unwrap_or_default(),
+                }
+            })
+            .collect();
+
+        Plan {
+            input_path: job.input_path.clone(),
+            output_path: job.output_path.clone(),
+            crop: job.crop.map(|x| x.filter()),
+            streams,
+        }
+    }
+}
@@ -0,0 +1,359 @@
+use crate::backend;
+use crate::error::Error;
+use crate::frontend::Stream;
+
+use ffmpeg::codec;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::path::Path;
+
+/// The policy that reproduces the built-in behaviour.
+const DEFAULT_POLICY: &str = include_str!("default_policy.toml");
+
+/// Decides, for each type of stream, which codecs are copied and what everything else is encoded to. Types of stream
+/// missing from a policy file use the default policy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CodecPolicy {
+    #[serde(default = "CodecPolicy::default_video")]
+    pub video: StreamPolicy,
+    #[serde(default = "CodecPolicy::default_audio")]
+    pub audio: StreamPolicy,
+    #[serde(default = "CodecPolicy::default_subtitle")]
+    pub subtitle: StreamPolicy,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StreamPolicy {
+    /// Streams with these codecs are copied.
+    pub copy: Vec<String>,
+    /// The first rule that matches a stream decides what it is encoded to.
+    pub rules: Vec<Rule>,
+    /// Used instead of the matching rule's target when encoding on the GPU. Only used for video.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gpu: Option<Target>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// The rule matches streams that meet all of these conditions.
+    #[serde(default, skip_serializing_if = "Conditions::is_empty")]
+    pub when: Conditions,
+    /// Matching streams are copied if this is not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<Target>,
+}
+
+/// Conditions on a stream. Unset conditions always match.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Conditions {
+    /// The stream's codec is one of these.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub codec: Vec<String>,
+    /// The stream's profile is one of these, for example "DTS-HD MA" or "High 10".
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub profile: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_channels: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_channels: Option<u16>,
+    /// In bits per second. Streams with an unknown bitrate never match bitrate conditions.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_bitrate: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_bitrate: Option<u64>,
+}
+
+/// What a stream is encoded to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Target {
+    pub codec: String,
+    /// Defaults to the usual encoder for `codec`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoder: Option<String>,
+    /// Passed to the encoder.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub options: BTreeMap<String, String>,
+}
+
+impl Default for CodecPolicy {
+    fn default() -> Self {
+        toml::from_str(DEFAULT_POLICY).expect("The default codec policy is invalid")
+    }
+}
+
+impl CodecPolicy {
+    fn default_video() -> StreamPolicy {
+        CodecPolicy::default().video
+    }
+
+    fn default_audio() -> StreamPolicy {
+        CodecPolicy::default().audio
+    }
+
+    fn default_subtitle() -> StreamPolicy {
+        CodecPolicy::default().subtitle
+    }
+
+    /// Read a policy from a TOML file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<CodecPolicy, Error> {
+        let path = path.as_ref();
+        let policy: CodecPolicy = toml::from_str(&std::fs::read_to_string(path)?)
+            .map_err(|e| Error::InvalidArguments(format!("Invalid codec policy '{}': {}", path.display(), e)))?;
+        policy.validate()?;
+        return Ok(policy);
+    }
+
+    /// Check that every codec named in the policy exists.
+    pub fn validate(&self) -> Result<(), Error> {
+        for policy in [&self.video, &self.audio, &self.subtitle].iter() {
+            let targets = policy.rules.iter().filter_map(|x| x.target.as_ref()).chain(policy.gpu.iter());
+            for target in targets {
+                target.codec_id()?;
+            }
+        }
+        return Ok(());
+    }
+}
+
+impl StreamPolicy {
+    /// Decide what `stream` is encoded to, or `None` if it is copied. If `force_encode` is set, the copy list and any
+    /// rules that copy are ignored.
+    pub fn decide(&self, stream: &Stream, force_encode: bool) -> Option<Target> {
+        let codec = stream.codec().name();
+        if !force_encode && self.copy.iter().any(|x| x.eq_ignore_ascii_case(codec)) {
+            return None;
+        }
+        let target = self
+            .rules
+            .iter()
+            .filter(|x| !force_encode || x.target.is_some())
+            .find(|x| x.when.matches(stream))
+            .and_then(|x| x.target.clone());
+        if force_encode && target.is_none() {
+            warn!(
+                "Stream {} ({}) should be re-encoded, but no rule in the codec policy has a target for it, so it is copied",
+                stream.index(),
+                codec
+            );
+        }
+        return target;
+    }
+}
+
+impl Conditions {
+    /// Whether there are no conditions, so every stream matches.
+    pub fn is_empty(&self) -> bool {
+        *self == Conditions::default()
+    }
+
+    pub fn matches(&self, stream: &Stream) -> bool {
+        let codec = stream.codec().name();
+        if !self.codec.is_empty() && !self.codec.iter().any(|x| x.eq_ignore_ascii_case(codec)) {
+            return false;
+        }
+
+        if !self.profile.is_empty() {
+            let profile = match stream.profile_name() {
+                Some(x) => x,
+                None => return false,
+            };
+            if !self.profile.iter().any(|x| x.eq_ignore_ascii_case(&profile)) {
+                return false;
+            }
+        }
+
+        if self.min_channels.is_some() || self.max_channels.is_some() {
+            match stream.channels() {
+                Some(x) if self.min_channels.is_none_or(|min| x >= min) && self.max_channels.is_none_or(|max| x <= max) => {}
+                _ => return false,
+            }
+        }
+
+        if self.min_bitrate.is_some() || self.max_bitrate.is_some() {
+            match stream.bit_rate() {
+                Some(x) if self.min_bitrate.is_none_or(|min| x >= min) && self.max_bitrate.is_none_or(|max| x <= max) => {}
+                _ => return false,
+            }
+        }
+
+        return true;
+    }
+}
+
+impl Target {
+    pub fn codec_id(&self) -> Result<codec::Id, Error> {
+        let unknown = || Error::InvalidArguments(format!("Unknown codec '{}' in codec policy", self.codec));
+        let name = CString::new(self.codec.as_str()).map_err(|_| unknown())?;
+        let descriptor = unsafe { ffmpeg::ffi::avcodec_descriptor_get_by_name(name.as_ptr()) };
+        if descriptor.is_null() {
+            return Err(unknown());
+        }
+        return Ok(codec::Id::from(unsafe { (*descriptor).id }));
+    }
+
+    /// The name of the encoder to use.
+    pub fn encoder(&self) -> Result<&str, Error> {
+        match self.encoder.as_ref() {
+            Some(x) => Ok(x),
+            None => backend::get_encoder(self.codec_id()?),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::{Audio, FieldOrder, Subtitle, Video};
+    use codec::profile::DTS;
+    use codec::{Id, Profile};
+
+    fn video(codec: Id) -> Stream {
+        Stream::Video(Video {
+            codec,
+            width: 1920,
+            height: 1080,
+            field_order: FieldOrder::Progressive,
+            ..Video::default()
+        })
+    }
+
+    fn audio(codec: Id, profile: Option<Profile>, channels: u16, bit_rate: Option<u64>) -> Stream {
+        Stream::Audio(Audio {
+            index: 1,
+            codec,
+            profile,
+            channels,
+            sample_rate: 48000,
+            bit_rate,
+            ..Audio::default()
+        })
+    }
+
+    fn subtitle(codec: Id) -> Stream {
+        Stream::Subtitle(Subtitle {
+            index: 2,
+            codec,
+            ..Subtitle::default()
+        })
+    }
+
+    #[test]
+    fn conditions_match() {
+        let dts_ma = audio(Id::DTS, Some(Profile::DTS(DTS::HD_MA)), 8, Some(3_000_000));
+        let ac3 = audio(Id::AC3, None, 2, None);
+        let h264 = video(Id::H264);
+        let cases: &[(&str, &Stream, bool)] = &[
+            ("", &ac3, true),
+            ("codec = ['ac3', 'eac3']", &ac3, true),
+            ("codec = ['AC3']", &ac3, true),
+            ("codec = ['dts']", &ac3, false),
+            ("profile = ['DTS-HD MA']", &dts_ma, true),
+            ("profile = ['dts-hd ma']", &dts_ma, true),
+            ("profile = ['DTS-HD HRA']", &dts_ma, false),
+            // Streams without a profile never match profile conditions
+            ("profile = ['DTS-HD MA']", &ac3, false),
+            ("min_channels = 6", &dts_ma, true),
+            ("min_channels = 6", &ac3, false),
+            ("max_channels = 2", &ac3, true),
+            ("max_channels = 6", &dts_ma, false),
+            ("min_channels = 2\nmax_channels = 2", &ac3, true),
+            ("min_channels = 1", &h264, false),
+            ("min_bitrate = 1000000", &dts_ma, true),
+            ("max_bitrate = 1000000", &dts_ma, false),
+            ("min_bitrate = 3000000\nmax_bitrate = 3000000", &dts_ma, true),
+            // Streams with an unknown bitrate never match bitrate conditions
+            ("max_bitrate = 1000000", &ac3, false),
+            ("codec = ['dts']\nprofile = ['DTS-HD MA']\nmin_channels = 6", &dts_ma, true),
+            ("codec = ['dts']\nprofile = ['DTS-HD MA']\nmin_channels = 10", &dts_ma, false),
+        ];
+        for (conditions, stream, expected) in cases.iter() {
+            let parsed: Conditions = toml::from_str(conditions).unwrap();
+            assert_eq!(parsed.matches(stream), *expected, "'{}' on {:?}", conditions, stream);
+        }
+    }
+
+    #[test]
+    fn first_matching_rule_decides() {
+        let policy: StreamPolicy = toml::from_str(
+            "copy = ['aac']
+            [[rules]]
+            when = { max_channels = 2 }
+            [[rules]]
+            when = { codec = ['ac3'] }
+            target = { codec = 'flac' }
+            [[rules]]
+            target = { codec = 'aac' }",
+        )
+        .unwrap();
+        let codec = |stream: &Stream, force_encode: bool| policy.decide(stream, force_encode).map(|x| x.codec);
+        let stereo = audio(Id::AC3, None, 2, None);
+        let surround = audio(Id::AC3, None, 6, None);
+
+        assert_eq!(codec(&audio(Id::AAC, None, 2, None), false), None);
+        assert_eq!(codec(&audio(Id::AAC, None, 2, None), true), Some("aac".to_string()));
+        // A rule without a target copies, unless the stream must be re-encoded
+        assert_eq!(codec(&stereo, false), None);
+        assert_eq!(codec(&stereo, true), Some("flac".to_string()));
+        assert_eq!(codec(&surround, false), Some("flac".to_string()));
+        assert_eq!(codec(&audio(Id::MP3, None, 2, None), false), None);
+        assert_eq!(codec(&audio(Id::MP3, None, 6, None), false), Some("aac".to_string()));
+    }
+
+    /// The decisions made before there were codec policies, which the default policy reproduces.
+    #[test]
+    fn default_policy_matches_built_in_decisions() {
+        let policy = CodecPolicy::default();
+        let cases: &[(Stream, Option<&str>)] = &[
+            (video(Id::H264), None),
+            (video(Id::HEVC), None),
+            (video(Id::MPEG2VIDEO), Some("h264")),
+            (video(Id::VC1), Some("h264")),
+            (audio(Id::AAC, None, 2, None), None),
+            (audio(Id::FLAC, None, 6, None), None),
+            (audio(Id::TRUEHD, None, 8, None), Some("flac")),
+            (audio(Id::DTS, Some(Profile::DTS(DTS::HD_MA)), 6, None), Some("flac")),
+            (audio(Id::DTS, Some(Profile::DTS(DTS::HD_HRA)), 6, None), Some("aac")),
+            (audio(Id::DTS, Some(Profile::DTS(DTS::Default)), 6, None), Some("aac")),
+            (audio(Id::AC3, None, 6, None), Some("aac")),
+            (audio(Id::EAC3, None, 6, None), Some("aac")),
+            (audio(Id::MP3, None, 2, None), Some("aac")),
+            (subtitle(Id::HDMV_PGS_SUBTITLE), None),
+            (subtitle(Id::DVD_SUBTITLE), None),
+            (subtitle(Id::SUBRIP), Some("ass")),
+            (subtitle(Id::ASS), Some("ass")),
+        ];
+        for (stream, expected) in cases.iter() {
+            let stream_policy = match stream {
+                Stream::Video(_) => &policy.video,
+                Stream::Audio(_) => &policy.audio,
+                Stream::Subtitle(_) => &policy.subtitle,
+            };
+            let target = stream_policy.decide(stream, false);
+            assert_eq!(target.as_ref().map(|x| x.codec.as_str()), *expected, "{:?}", stream);
+        }
+
+        let encoder = |target: Option<&Target>| target.map(|x| x.encoder().unwrap().to_string());
+        let mpeg2 = policy.video.decide(&video(Id::MPEG2VIDEO), false);
+        assert_eq!(encoder(mpeg2.as_ref()).as_deref(), Some("libx264"));
+        assert_eq!(policy.video.gpu.as_ref().map(|x| x.codec.as_str()), Some("hevc"));
+        assert_eq!(encoder(policy.video.gpu.as_ref()).as_deref(), Some("hevc_nvenc"));
+        let ac3 = policy.audio.decide(&audio(Id::AC3, None, 6, None), false);
+        assert_eq!(encoder(ac3.as_ref()).as_deref(), Some("libfdk_aac"));
+    }
+
+    #[test]
+    fn missing_sections_use_the_default_policy() {
+        let policy: CodecPolicy = toml::from_str("[audio]\ncopy = ['ac3']").unwrap();
+        assert_eq!(policy.audio.copy, vec!["ac3".to_string()]);
+        assert_eq!(policy.video.copy, CodecPolicy::default().video.copy);
+        assert_eq!(policy.subtitle.copy, CodecPolicy::default().subtitle.copy);
+        policy.validate().unwrap();
+    }
+}
@@ -0,0 +1,122 @@
+use crate::error::Error;
+use crate::options::{Denoise, Libx264Tune, Options};
+use crate::policy::{Conditions, Rule, Target};
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The presets that are always available.
+const DEFAULT_PRESETS: &str = include_str!("default_presets.toml");
+
+/// A named bundle of settings for a type of source. Unset fields leave the option unchanged.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Preset {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crf: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tune: Option<Libx264Tune>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crop: Option<String>,
+    /// `true` always deinterlaces, `false` never does.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deinterlace: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub denoise: Option<Denoise>,
+    /// Video larger than this is scaled down to fit.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub force_reencode: Option<bool>,
+    /// What video that is encoded is encoded to, in place of the codec policy's video rules.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub video: Option<Target>,
+}
+
+/// Presets by name.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Presets(pub BTreeMap<String, Preset>);
+
+impl Default for Presets {
+    fn default() -> Self {
+        toml::from_str(DEFAULT_PRESETS).expect("The default presets are invalid")
+    }
+}
+
+impl Presets {
+    /// Read presets from a TOML file, in addition to these. Presets in the file replace those with the same name.
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        let presets: Presets = toml::from_str(&std::fs::read_to_string(path)?)
+            .map_err(|e| Error::InvalidArguments(format!("Invalid presets '{}': {}", path.display(), e)))?;
+        return self.extend(presets.0);
+    }
+
+    /// Add presets to these, replacing any with the same name, once they have been checked.
+    pub fn extend(&mut self, presets: BTreeMap<String, Preset>) -> Result<(), Error> {
+        for preset in presets.values() {
+            preset.validate()?;
+        }
+        self.0.extend(presets);
+        return Ok(());
+    }
+
+    pub fn get(&self, name: &str) -> Result<&Preset, Error> {
+        return self.0.get(name).ok_or_else(|| {
+            let names: Vec<&str> = self.0.keys().map(String::as_str).collect();
+            Error::InvalidArguments(format!("Unknown preset '{}'. Available presets are: {}", name, names.join(", ")))
+        });
+    }
+}
+
+impl Preset {
+    /// Check that the codec the preset encodes video to exists.
+    pub fn validate(&self) -> Result<(), Error> {
+        if let Some(target) = self.video.as_ref() {
+            target.codec_id()?;
+        }
+        return Ok(());
+    }
+
+    /// Overwrite the options this preset sets.
+    pub fn apply(&self, options: &mut Options) {
+        if let Some(target) = self.video.as_ref() {
+            options.policy.video.rules = vec![Rule {
+                when: Conditions::default(),
+                target: Some(target.clone()),
+            }];
+        }
+        if let Some(crf) = self.crf {
+            options.crf = crf;
+        }
+        if let Some(tune) = self.tune {
+            options.tune = Some(tune);
+        }
+        if let Some(crop) = self.crop.as_ref() {
+            options.crop = Some(crop.clone());
+            options.auto_crop = false;
+        }
+        if let Some(deinterlace) = self.deinterlace {
+            options.force_deinterlace = deinterlace;
+            options.no_deinterlace = !deinterlace;
+        }
+        if let Some(denoise) = self.denoise {
+            options.denoise = Some(denoise);
+        }
+        if let Some(max_width) = self.max_width {
+            options.max_width = Some(max_width);
+        }
+        if let Some(max_height) = self.max_height {
+            options.max_height = Some(max_height);
+        }
+        if let Some(force_reencode) = self.force_reencode {
+            options.force_reencode = force_reencode;
+        }
+    }
+}
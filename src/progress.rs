@@ -0,0 +1,197 @@
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+const BATCH_TEMPLATE: &str = "{prefix:>12} [{bar:40}] {percent:>3}% {elapsed_precise} ETA {eta_precise}";
+const FILE_TEMPLATE: &str = "{prefix:>12} [{bar:40}] {percent:>3}% {msg} ETA {eta}";
+const FILE_SPINNER_TEMPLATE: &str = "{prefix:>12} {spinner} {elapsed_precise} {msg}";
+
+/// A single block of ffmpeg's `-progress` output.
+#[derive(Debug, Default, Clone)]
+pub struct ProgressUpdate {
+    pub frame: Option<u64>,
+    pub out_time: Option<Duration>,
+    pub speed: Option<f64>,
+}
+
+/// Parses the `key=value` lines that ffmpeg writes when run with `-progress`.
+#[derive(Debug, Default)]
+pub struct ProgressParser {
+    current: ProgressUpdate,
+}
+
+impl ProgressParser {
+    /// Feed a line to the parser. Returns an update once a full block has been read.
+    pub fn parse_line(&mut self, line: &str) -> Option<ProgressUpdate> {
+        let (key, value) = line.trim().split_once('=')?;
+
+        match key {
+            "frame" => self.current.frame = value.parse().ok(),
+            // Despite the name, out_time_ms is also in microseconds
+            "out_time_us" | "out_time_ms" => self.current.out_time = value.parse().ok().map(Duration::from_micros),
+            "speed" => self.current.speed = value.trim_end_matches('x').trim().parse().ok(),
+            "progress" => return Some(std::mem::take(&mut self.current)),
+            _ => {}
+        }
+
+        return None;
+    }
+}
+
+/// Progress bars for a whole batch of files.
+pub struct BatchProgress {
+    multi: MultiProgress,
+    total: ProgressBar,
+}
+
+impl BatchProgress {
+    /// `total_duration` is the sum of the durations of all the files in the batch.
+    pub fn new(total_duration: Duration, hidden: bool) -> BatchProgress {
+        let multi = if hidden {
+            MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+        } else {
+            MultiProgress::new()
+        };
+
+        let total = multi.add(ProgressBar::new(total_duration.as_millis() as u64));
+        total.set_style(ProgressStyle::with_template(BATCH_TEMPLATE).unwrap().progress_chars("=> "));
+        total.set_prefix("Total");
+
+        BatchProgress { multi, total }
+    }
+
+    /// Add a progress bar for a single file. If the duration of the file is not known, a spinner is shown instead.
+    pub fn start_file(&self, name: &str, duration: Option<Duration>) -> FileProgress {
+        let bar = match duration {
+            Some(duration) => {
+                let bar = ProgressBar::new(duration.as_millis() as u64);
+                bar.set_style(ProgressStyle::with_template(FILE_TEMPLATE).unwrap().progress_chars("=> "));
+                bar
+            }
+            None => {
+                let bar = ProgressBar::new_spinner();
+                bar.set_style(ProgressStyle::with_template(FILE_SPINNER_TEMPLATE).unwrap());
+                bar.enable_steady_tick(Duration::from_millis(100));
+                bar
+            }
+        };
+        let bar = self.multi.insert_before(&self.total, bar);
+        bar.set_prefix(name.to_string());
+
+        FileProgress {
+            bar,
+            total: self.total.clone(),
+            position: AtomicU64::new(0),
+        }
+    }
+
+    pub fn finish(&self) {
+        self.total.finish();
+    }
+}
+
+/// The progress bar for a single file. Advancing it also advances the batch progress bar.
+pub struct FileProgress {
+    bar: ProgressBar,
+    total: ProgressBar,
+    position: AtomicU64,
+}
+
+impl FileProgress {
+    pub fn update(&self, update: &ProgressUpdate) {
+        if let Some(out_time) = update.out_time {
+            self.set_time(out_time);
+        }
+
+        let mut message = Vec::new();
+        if let Some(frame) = update.frame {
+            message.push(format!("frame {}", frame));
+        }
+        if let Some(speed) = update.speed {
+            message.push(format!("{:.2}x", speed));
+        }
+        self.bar.set_message(message.join(" "));
+        self.bar.tick();
+    }
+
+    /// Advance the progress bar to `time` into the file. Going backwards is ignored.
+    pub fn set_time(&self, time: Duration) {
+        let mut position = time.as_millis() as u64;
+        if let Some(length) = self.bar.length() {
+            position = position.min(length);
+        }
+
+        let previous = self.position.fetch_max(position, Ordering::Relaxed);
+        if position > previous {
+            self.bar.set_position(position);
+            if self.bar.length().is_some() {
+                self.total.inc(position - previous);
+            }
+        }
+    }
+
+    /// Complete the progress bar, counting any remaining time towards the batch total.
+    pub fn finish(&self) {
+        if let Some(length) = self.bar.length() {
+            self.set_time(Duration::from_millis(length));
+        }
+        self.bar.finish_and_clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(lines: &[&str]) -> Vec<ProgressUpdate> {
+        let mut parser = ProgressParser::default();
+        return lines.iter().filter_map(|x| parser.parse_line(x)).collect();
+    }
+
+    #[test]
+    fn reads_a_block() {
+        let updates = parse(&[
+            "frame=240",
+            "fps=48.00",
+            "out_time_us=10010000",
+            "out_time_ms=10010000",
+            "out_time=00:00:10.010000",
+            "speed=2.01x",
+            "progress=continue",
+        ]);
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].frame, Some(240));
+        assert_eq!(updates[0].out_time, Some(Duration::from_micros(10_010_000)));
+        assert_eq!(updates[0].speed, Some(2.01));
+    }
+
+    #[test]
+    fn ignores_unavailable_values() {
+        let updates = parse(&["frame=0", "out_time_us=N/A", "out_time_ms=N/A", "speed=N/A", "progress=continue"]);
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].frame, Some(0));
+        assert_eq!(updates[0].out_time, None);
+        assert_eq!(updates[0].speed, None);
+    }
+
+    #[test]
+    fn ends_with_the_last_block() {
+        let updates = parse(&[
+            "out_time_us=5000000",
+            "progress=continue",
+            "out_time_us=7500000",
+            "speed=  1.5x",
+            "progress=end",
+        ]);
+        assert_eq!(updates.len(), 2);
+        assert_eq!(updates[1].out_time, Some(Duration::from_millis(7500)));
+        assert_eq!(updates[1].speed, Some(1.5));
+        // Each block starts afresh
+        assert_eq!(updates[1].frame, None);
+    }
+
+    #[test]
+    fn ignores_lines_that_are_not_values() {
+        assert!(parse(&["", "garbage", "progress"]).is_empty());
+    }
+}
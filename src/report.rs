@@ -0,0 +1,191 @@
+use crate::frontend::{self, MediaInfo, Rational, Stream};
+use crate::options::Options;
+use crate::plan::{Action, StreamType};
+
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// What is known about a file, and what converting it would do to each of its streams, in a form that can be
+/// serialized or printed.
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub path: PathBuf,
+    pub format: String,
+    pub format_long_name: String,
+    /// In seconds.
+    pub duration: Option<f64>,
+    pub bit_rate: Option<u64>,
+    pub title: Option<String>,
+    pub streams: Vec<StreamReport>,
+}
+
+/// A single stream, with the properties that apply to its type.
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamReport {
+    pub index: usize,
+    #[serde(rename = "type")]
+    pub stream_type: StreamType,
+    pub codec: String,
+    pub profile: Option<String>,
+    pub language: Option<String>,
+    pub title: Option<String>,
+    pub disposition: Vec<&'static str>,
+    pub bit_rate: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub video: Option<VideoReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio: Option<AudioReport>,
+    /// What converting the file would do to the stream, or `None` if it would be dropped.
+    pub action: Option<Action>,
+    /// The codec the stream would be encoded to, if it is not copied.
+    pub target_codec: Option<String>,
+    pub encoder: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VideoReport {
+    pub width: u32,
+    pub height: u32,
+    /// As "width:height".
+    pub sample_aspect_ratio: Option<String>,
+    pub display_aspect_ratio: Option<String>,
+    /// In frames per second.
+    pub avg_frame_rate: Option<f64>,
+    pub real_frame_rate: Option<f64>,
+    pub pix_fmt: Option<String>,
+    pub bit_depth: Option<u8>,
+    pub color_transfer: Option<String>,
+    pub hdr: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AudioReport {
+    pub channels: u16,
+    pub channel_layout: Option<String>,
+    /// In Hz.
+    pub sample_rate: u32,
+}
+
+fn ratio(x: Rational) -> String {
+    format!("{}:{}", x.numerator(), x.denominator())
+}
+
+impl Report {
+    /// Describe a probed file, deciding which streams to keep and how to convert them in the same way as
+    /// [`create_job`](crate::create_job) would.
+    pub fn new(path: &Path, info: &MediaInfo, args: &Options) -> Report {
+        let mappings = frontend::get_stream_mappings(&info.streams, args);
+        let crop = args.crop.as_ref().and_then(|x| x.parse().ok());
+        let codecs = frontend::get_codec_mapping(&mappings, crop, args);
+
+        let streams = info
+            .streams
+            .iter()
+            .map(|stream| {
+                let kept = mappings.iter().any(|x| x.index() == stream.index());
+                let target = codecs.get(&stream.index()).cloned().flatten();
+                let action = match (kept, target.is_some()) {
+                    (false, _) => None,
+                    (true, false) => Some(Action::Copy),
+                    (true, true) => Some(Action::Encode),
+                };
+                let (stream_type, language) = match stream {
+                    Stream::Video(_) => (StreamType::Video, None),
+                    Stream::Audio(x) => (StreamType::Audio, x.lang.clone()),
+                    Stream::Subtitle(x) => (StreamType::Subtitle, x.lang.clone()),
+                };
+
+                StreamReport {
+                    index: stream.index(),
+                    stream_type,
+                    codec: stream.codec().name().to_string(),
+                    profile: stream.profile_name(),
+                    language,
+                    title: stream.title().map(str::to_string),
+                    disposition: stream.disposition().names(),
+                    bit_rate: stream.bit_rate(),
+                    video: stream.as_video().map(|x| VideoReport {
+                        width: x.width,
+                        height: x.height,
+                        sample_aspect_ratio: x.sample_aspect_ratio.map(ratio),
+                        display_aspect_ratio: x.display_aspect_ratio().map(ratio),
+                        avg_frame_rate: x.avg_frame_rate.map(f64::from),
+                        real_frame_rate: x.real_frame_rate.map(f64::from),
+                        pix_fmt: x.pix_fmt.clone(),
+                        bit_depth: x.bit_depth,
+                        color_transfer: x.color_transfer.clone(),
+                        hdr: x.is_hdr(),
+                    }),
+                    audio: match stream {
+                        Stream::Audio(x) => Some(AudioReport {
+                            channels: x.channels,
+                            channel_layout: x.channel_layout.clone(),
+                            sample_rate: x.sample_rate,
+                        }),
+                        _ => None,
+                    },
+                    action,
+                    target_codec: target.as_ref().map(|x| x.codec.clone()),
+                    encoder: target.as_ref().and_then(|x| x.encoder().ok()).map(str::to_string),
+                }
+            })
+            .collect();
+
+        Report {
+            path: path.to_path_buf(),
+            format: info.format_name.clone(),
+            format_long_name: info.format_long_name.clone(),
+            duration: info.duration.map(|x| x.as_secs_f64()),
+            bit_rate: info.bit_rate,
+            title: info.title.clone(),
+            streams,
+        }
+    }
+}
+
+impl StreamReport {
+    /// The properties of the stream, in a few words.
+    pub fn details(&self) -> String {
+        let mut details = Vec::new();
+        if let Some(x) = self.video.as_ref() {
+            details.push(format!("{}x{}", x.width, x.height));
+            if let Some(dar) = x.display_aspect_ratio.as_ref() {
+                details.push(format!("DAR {}", dar));
+            }
+            if let Some(sar) = x.sample_aspect_ratio.as_ref().filter(|x| *x != "1:1") {
+                details.push(format!("SAR {}", sar));
+            }
+            if let Some(rate) = x.avg_frame_rate {
+                details.push(format!("{:.3}fps", rate));
+            }
+            if let Some(depth) = x.bit_depth {
+                details.push(format!("{}-bit", depth));
+            }
+            if x.hdr {
+                details.push("HDR".to_string());
+            }
+        }
+        if let Some(x) = self.audio.as_ref() {
+            details.push(x.channel_layout.clone().unwrap_or_else(|| format!("{}ch", x.channels)));
+            if x.sample_rate > 0 {
+                details.push(format!("{}Hz", x.sample_rate));
+            }
+        }
+        if let Some(bit_rate) = self.bit_rate {
+            details.push(format!("{}kb/s", bit_rate / 1000));
+        }
+        return details.join(" ");
+    }
+
+    /// What converting the file would do to the stream, in a few words.
+    pub fn decision(&self) -> String {
+        match (self.action, self.target_codec.as_ref()) {
+            (None, _) => "drop".to_string(),
+            (Some(Action::Copy), _) | (Some(Action::Encode), None) => "copy".to_string(),
+            (Some(Action::Encode), Some(codec)) => match self.encoder.as_ref() {
+                Some(encoder) => format!("encode to {} ({})", codec, encoder),
+                None => format!("encode to {}", codec),
+            },
+        }
+    }
+}
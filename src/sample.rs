@@ -0,0 +1,95 @@
+use crate::error::Error;
+
+use ffmpeg::{filter, frame, rescale, Rational, Rescale};
+use std::path::Path;
+
+/// The number of points in the file that frames are sampled from.
+const SAMPLE_POINTS: i64 = 12;
+/// Frames are sampled from between these percentages of the way through the file, to skip intros and credits.
+const SAMPLE_START: i64 = 10;
+const SAMPLE_END: i64 = 90;
+
+/// The arguments of a `buffer` filter that feeds the frames `decoder` produces, with timestamps in `time_base`, into a
+/// filter graph.
+pub(crate) fn video_buffer_args(decoder: &ffmpeg::decoder::Video, time_base: Rational) -> String {
+    let pixel_format: ffmpeg::ffi::AVPixelFormat = decoder.format().into();
+    return format!(
+        "video_size={}x{}:pix_fmt={}:time_base={}:pixel_aspect={}",
+        decoder.width(),
+        decoder.height(),
+        pixel_format as std::os::raw::c_int,
+        time_base,
+        decoder.aspect_ratio()
+    );
+}
+
+/// Decode runs of `frames_per_point` consecutive frames from points across a video stream, pass them through the
+/// filter graph described by `filter_spec`, and call `on_frame` with each filtered frame. Returns the width and height
+/// of the video.
+pub(crate) fn sample_frames<F>(
+    path: &Path,
+    stream_index: usize,
+    filter_spec: &str,
+    frames_per_point: usize,
+    mut on_frame: F,
+) -> Result<(u32, u32), Error>
+where
+    F: FnMut(&frame::Video),
+{
+    let mut ictx = crate::open(path)?;
+
+    let (duration, mut decoder, mut graph) = {
+        let ist = ictx
+            .stream(stream_index)
+            .ok_or_else(|| Error::UnsupportedStream(format!("No stream {} in '{}'", stream_index, path.display())))?;
+        // libav* durations are in microseconds, and negative if unknown. Without one, every sample would be taken from
+        // the start of the file.
+        let duration = match ictx.duration() {
+            x if x > 0 => x,
+            _ => ist.duration().rescale(ist.time_base(), rescale::TIME_BASE),
+        };
+        if duration <= 0 {
+            return Err(Error::UnsupportedStream(format!("The duration of '{}' is unknown", path.display())));
+        }
+        let decoder = ist.codec().decoder().video()?;
+
+        let mut graph = filter::Graph::new();
+        let buffer_args = video_buffer_args(&decoder, ist.time_base());
+        graph.add(&filter::find("buffer").unwrap(), "in", &buffer_args)?;
+        graph.add(&filter::find("buffersink").unwrap(), "out", "")?;
+        graph.output("in", 0)?.input("out", 0)?.parse(filter_spec)?;
+        graph.validate()?;
+        (duration, decoder, graph)
+    };
+
+    for point in 0..SAMPLE_POINTS {
+        // Seek targets are in microseconds when no stream is given, as durations are
+        let percent = SAMPLE_START + (SAMPLE_END - SAMPLE_START) * point / (SAMPLE_POINTS - 1);
+        let position = duration * percent / 100;
+        if ictx.seek(position, ..position).is_err() {
+            continue;
+        }
+        decoder.flush();
+
+        let mut frames = 0;
+        for (stream, packet) in ictx.packets() {
+            if stream.index() != stream_index || decoder.send_packet(&packet).is_err() {
+                continue;
+            }
+            let mut decoded = frame::Video::empty();
+            while frames < frames_per_point && decoder.receive_frame(&mut decoded).is_ok() {
+                frames += 1;
+                graph.get("in").unwrap().source().add(&decoded)?;
+                let mut filtered = frame::Video::empty();
+                while graph.get("out").unwrap().sink().frame(&mut filtered).is_ok() {
+                    on_frame(&filtered);
+                }
+            }
+            if frames >= frames_per_point {
+                break;
+            }
+        }
+    }
+
+    return Ok((decoder.width(), decoder.height()));
+}
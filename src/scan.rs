@@ -0,0 +1,163 @@
+use crate::error::Error;
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use log::{debug, info};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Files that aren't worth probing, excluded unless the configuration says otherwise.
+pub const DEFAULT_EXCLUDES: &[&str] = &[
+    "*.gif",
+    "*.jpg",
+    "*.md",
+    "*.nfo",
+    "*.png",
+    "*.py",
+    "*.rar",
+    "*.r[0-9][0-9]",
+    "*.sfv",
+    "*.srr",
+    "*.txt",
+];
+
+/// Anything shorter than this is a still image or a broken file, not something to convert.
+const MIN_DURATION: Duration = Duration::from_secs(1);
+
+/// The files found by [`get_entries`], sorted by path.
+#[derive(Debug, Default)]
+pub struct Entries {
+    /// The media files to convert.
+    pub media: Vec<PathBuf>,
+    /// Files that could not be probed, and why.
+    pub failed: Vec<(PathBuf, Error)>,
+}
+
+/// Decides which paths are considered, before any probing is done. Globs are matched against the path relative to the
+/// target directory.
+pub struct Filter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl Filter {
+    /// If `include` is empty every file is included.
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Filter, globset::Error> {
+        let include = if include.is_empty() {
+            None
+        } else {
+            Some(build_glob_set(include.iter().map(String::as_str))?)
+        };
+        let exclude = build_glob_set(exclude.iter().map(String::as_str))?;
+
+        return Ok(Filter { include, exclude });
+    }
+
+    fn is_excluded(&self, relative_path: &Path) -> bool {
+        return self.exclude.is_match(relative_path);
+    }
+
+    fn is_included(&self, relative_path: &Path) -> bool {
+        return self.include.as_ref().is_none_or(|x| x.is_match(relative_path)) && !self.is_excluded(relative_path);
+    }
+}
+
+fn build_glob_set<'a>(globs: impl Iterator<Item = &'a str>) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(Glob::new(glob)?);
+    }
+    return builder.build();
+}
+
+/// Files and directories whose names start with '.' are ignored.
+fn is_hidden(path: &Path) -> bool {
+    path.file_name().is_some_and(|x| x.to_string_lossy().starts_with('.'))
+}
+
+/// Probe `path`, returning why it is not a media file, if it isn't one. Files that can't be probed at all, because
+/// they are unreadable or corrupt, are errors rather than non-media files.
+fn probe(path: &Path) -> Result<Option<&'static str>, Error> {
+    let file = match crate::open(path) {
+        Ok(x) => x,
+        // No demuxer recognises the file, as with subtitle indexes, DVD menus and anything else that isn't media
+        Err(Error::Probe {
+            source: ffmpeg::Error::InvalidData,
+            ..
+        }) => return Ok(Some("not a recognised media format")),
+        Err(e) => return Err(e),
+    };
+
+    let has_media = file
+        .streams()
+        .any(|x| matches!(x.parameters().medium(), ffmpeg::media::Type::Video | ffmpeg::media::Type::Audio));
+    if !has_media {
+        return Ok(Some("no video or audio streams"));
+    }
+
+    // libav* durations are in microseconds. An unknown duration is negative.
+    if file.duration() < MIN_DURATION.as_micros() as i64 {
+        return Ok(Some("too short, or no duration"));
+    }
+
+    return Ok(None);
+}
+
+fn walk(root: &Path, dir: &Path, recursive: bool, output_root: &Path, filter: &Filter, entries: &mut Entries) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let relative_path = path.strip_prefix(root).unwrap_or(&path);
+        if is_hidden(&path) {
+            continue;
+        }
+        if path.is_dir() {
+            // Don't descend into the output of this (or a previous) run
+            if recursive && path != output_root && !filter.is_excluded(relative_path) {
+                walk(root, &path, recursive, output_root, filter, entries)?;
+            }
+        } else if !filter.is_included(relative_path) {
+            debug!("Skipping '{}': excluded", relative_path.display());
+        } else {
+            match probe(&path) {
+                Ok(None) => entries.media.push(path),
+                Ok(Some(reason)) => info!("Skipping '{}': not a media file ({})", relative_path.display(), reason),
+                Err(e) => entries.failed.push((path, e)),
+            }
+        }
+    }
+    return Ok(());
+}
+
+/// Find the media files to convert in `path`. If `recursive` is set, subdirectories (other than `output_root`) are
+/// searched too.
+pub fn get_entries(path: &Path, recursive: bool, output_root: &Path, filter: &Filter) -> std::io::Result<Entries> {
+    let mut entries = Entries::default();
+    walk(path, path, recursive, output_root, filter, &mut entries)?;
+    entries.media.sort();
+    entries.failed.sort_by(|a, b| a.0.cmp(&b.0));
+    return Ok(entries);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unrecognised_files_are_not_media() {
+        ffmpeg::init().unwrap();
+        let path = std::env::temp_dir().join(format!("videoconverter-{}-setup.exe", std::process::id()));
+        let mut contents = b"MZ\x90\x00\x03\x00\x00\x00\x04\x00\x00\x00\xff\xff".to_vec();
+        contents.resize(4096, 0);
+        std::fs::write(&path, contents).unwrap();
+
+        let result = probe(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap(), Some("not a recognised media format"));
+    }
+
+    #[test]
+    fn missing_files_fail() {
+        ffmpeg::init().unwrap();
+        let path = std::env::temp_dir().join(format!("videoconverter-{}-missing.mkv", std::process::id()));
+        assert!(matches!(probe(&path), Err(Error::Probe { .. })));
+    }
+}
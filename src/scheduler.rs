@@ -0,0 +1,154 @@
+use crate::logging;
+use crate::logging::JobLog;
+
+use std::sync::{Condvar, Mutex};
+
+/// Remuxes only copy streams, so are limited by I/O. Encodes are limited by the CPU (or GPU).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobKind {
+    Remux,
+    Encode,
+}
+
+/// How many jobs may run at once.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub total: usize,
+    pub remux: usize,
+    pub encode: usize,
+}
+
+struct Finished<E> {
+    result: Option<Result<(), E>>,
+    log: Option<JobLog>,
+}
+
+struct State<T, E> {
+    pending: Vec<Option<(JobKind, T)>>,
+    running_remux: usize,
+    running_encode: usize,
+    stopped: bool,
+    finished: Vec<Option<Finished<E>>>,
+    next_to_emit: usize,
+}
+
+impl<T, E> State<T, E> {
+    fn can_start(&self, kind: JobKind, limits: &Limits) -> bool {
+        let running = self.running_remux + self.running_encode;
+        running < limits.total
+            && match kind {
+                JobKind::Remux => self.running_remux < limits.remux,
+                JobKind::Encode => self.running_encode < limits.encode,
+            }
+    }
+
+    fn running(&mut self, kind: JobKind) -> &mut usize {
+        match kind {
+            JobKind::Remux => &mut self.running_remux,
+            JobKind::Encode => &mut self.running_encode,
+        }
+    }
+
+    /// Emit the logs of finished jobs, in the order the jobs were submitted.
+    fn emit_logs(&mut self) {
+        while let Some(Some(finished)) = self.finished.get_mut(self.next_to_emit) {
+            if let Some(log) = finished.log.take() {
+                log.emit();
+            }
+            self.next_to_emit += 1;
+        }
+    }
+}
+
+/// Run `jobs` through `f` on a pool of threads, respecting `limits`.
+///
+/// Jobs are started in order, skipping over any that would exceed the limit for their kind. When more than one job can
+/// run at once, everything a job logs is held back and emitted as one block, in the order the jobs were submitted.
+/// If `fail_fast` is set, no more jobs are started once a job fails. The returned results are in the same order as
+/// `jobs`, with `None` for jobs that were not run.
+pub fn run<T, E, F>(jobs: Vec<(JobKind, T)>, limits: Limits, fail_fast: bool, f: F) -> Vec<Option<Result<(), E>>>
+where
+    T: Send,
+    E: Send,
+    F: Fn(T) -> Result<(), E> + Sync,
+{
+    let limits = Limits {
+        total: limits.total.max(1),
+        remux: limits.remux.max(1),
+        encode: limits.encode.max(1),
+    };
+    let capture_logs = limits.total > 1;
+    let job_count = jobs.len();
+
+    let state = Mutex::new(State {
+        pending: jobs.into_iter().map(Some).collect(),
+        running_remux: 0,
+        running_encode: 0,
+        stopped: false,
+        finished: (0..job_count).map(|_| None).collect(),
+        next_to_emit: 0,
+    });
+    let job_finished = Condvar::new();
+
+    let worker = || loop {
+        let next = {
+            let mut state = state.lock().unwrap();
+            loop {
+                if state.stopped || state.pending.iter().all(Option::is_none) {
+                    break None;
+                }
+                let startable = state
+                    .pending
+                    .iter()
+                    .position(|job| matches!(job, Some((kind, _)) if state.can_start(*kind, &limits)));
+                if let Some(index) = startable {
+                    let (kind, job) = state.pending[index].take().unwrap();
+                    *state.running(kind) += 1;
+                    break Some((index, kind, job));
+                }
+                state = job_finished.wait(state).unwrap();
+            }
+        };
+
+        let (index, kind, job) = match next {
+            Some(x) => x,
+            None => return,
+        };
+
+        let (result, log) = if capture_logs {
+            let (result, log) = logging::capture(|| f(job));
+            (result, Some(log))
+        } else {
+            (f(job), None)
+        };
+
+        let mut state = state.lock().unwrap();
+        *state.running(kind) -= 1;
+        if fail_fast && result.is_err() {
+            state.stopped = true;
+            // Jobs that will never be started must not hold back the logs of later jobs
+            for skipped in 0..job_count {
+                if state.pending[skipped].take().is_some() {
+                    state.finished[skipped] = Some(Finished { result: None, log: None });
+                }
+            }
+        }
+        state.finished[index] = Some(Finished { result: Some(result), log });
+        state.emit_logs();
+        job_finished.notify_all();
+    };
+
+    std::thread::scope(|scope| {
+        for _ in 0..limits.total.min(job_count) {
+            scope.spawn(&worker);
+        }
+    });
+
+    return state
+        .into_inner()
+        .unwrap()
+        .finished
+        .into_iter()
+        .map(|finished| finished.and_then(|x| x.result))
+        .collect();
+}
@@ -1,13 +1,14 @@
-use crate::interface::TVOptions;
 use std::fs::File;
 use std::io::BufRead;
-use std::io::{stdin, stdout, Write};
+use std::io::{stderr, stdin, Write};
+use videoconverter::options::TVOptions;
+use videoconverter::Error;
 
 pub fn prompt(prompt: &str) -> std::io::Result<String> {
     let mut buf = String::new();
-    print!("{}: ", prompt);
+    eprint!("{}: ", prompt);
 
-    stdout().lock().flush()?;
+    stderr().lock().flush()?;
     stdin().read_line(&mut buf)?;
     buf.truncate(buf.trim_end().len());
     return Ok(buf);
@@ -17,12 +18,12 @@ pub fn confirm(prompt: &str, default: bool) -> std::io::Result<bool> {
     let mut buf = String::new();
     loop {
         if default {
-            print!("{} (Y/n) ", prompt);
+            eprint!("{} (Y/n) ", prompt);
         } else {
-            print!("{} (y/N) ", prompt);
+            eprint!("{} (y/N) ", prompt);
         }
 
-        stdout().lock().flush()?;
+        stderr().lock().flush()?;
         stdin().read_line(&mut buf)?;
         buf.make_ascii_lowercase();
 
@@ -30,7 +31,7 @@ pub fn confirm(prompt: &str, default: bool) -> std::io::Result<bool> {
             "y" | "yes" => return Ok(true),
             "n" | "no" => return Ok(false),
             "" => return Ok(default),
-            _ => println!("Invalid response."),
+            _ => eprintln!("Invalid response."),
         }
     }
 }
@@ -46,14 +47,15 @@ pub fn write_state(tv_options: &TVOptions) -> std::io::Result<()> {
     )
 }
 
-pub fn read_state() -> Result<TVOptions, Box<dyn std::error::Error>> {
+pub fn read_state() -> Result<TVOptions, Error> {
     let file = File::open("/tmp/videoconverter.state")?;
     let reader = std::io::BufReader::new(file);
-    let lines: Vec<String> = reader.lines().map(|x| x.unwrap()).collect();
+    let lines = reader.lines().collect::<std::io::Result<Vec<String>>>()?;
+    let invalid = || Error::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, "Malformed statefile"));
     let enabled = true;
-    let title = Some(lines[0].clone());
-    let season = Some(lines[1].parse::<usize>()?);
-    let episode = Some(lines[2].parse::<usize>()?);
+    let title = Some(lines.get(0).ok_or_else(invalid)?.clone());
+    let season = Some(lines.get(1).and_then(|x| x.parse::<usize>().ok()).ok_or_else(invalid)?);
+    let episode = Some(lines.get(2).and_then(|x| x.parse::<usize>().ok()).ok_or_else(invalid)?);
 
     Ok(TVOptions {
         enabled,
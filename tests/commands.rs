@@ -0,0 +1,218 @@
+//! Plans jobs for files made up of known streams with the ffmpeg backend, and checks the commands it would run.
+
+use ffmpeg_next::codec::Id;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use videoconverter::backend::cli::CliBackend;
+use videoconverter::backend::recording::RecordingBackend;
+use videoconverter::frontend::{Audio, Disposition, FieldOrder, MediaInfo, Stream, Subtitle, Video};
+use videoconverter::options::Tonemap;
+use videoconverter::progress::BatchProgress;
+use videoconverter::{backend, Options};
+
+fn video(index: usize, codec: Id, width: u32, height: u32) -> Video {
+    Video {
+        index,
+        codec,
+        width,
+        height,
+        field_order: FieldOrder::Progressive,
+        pix_fmt: Some("yuv420p".to_string()),
+        bit_depth: Some(8),
+        ..Video::default()
+    }
+}
+
+fn audio(index: usize, codec: Id, lang: &str) -> Stream {
+    Stream::Audio(Audio {
+        index,
+        codec,
+        lang: Some(lang.to_string()),
+        channels: 6,
+        channel_layout: Some("5.1(side)".to_string()),
+        sample_rate: 48000,
+        ..Audio::default()
+    })
+}
+
+fn subtitle(index: usize, codec: Id, lang: &str, disposition: Disposition) -> Stream {
+    Stream::Subtitle(Subtitle {
+        index,
+        codec,
+        lang: Some(lang.to_string()),
+        disposition,
+        ..Subtitle::default()
+    })
+}
+
+/// The ffmpeg command that converting a file made up of `streams` with `options` would run, as `--simulate` records it.
+fn command(streams: Vec<Stream>, options: &Options) -> Vec<String> {
+    ffmpeg_next::init().unwrap();
+    let info = MediaInfo {
+        format_name: "matroska,webm".to_string(),
+        format_long_name: "Matroska / WebM".to_string(),
+        duration: Some(Duration::from_secs(60)),
+        bit_rate: None,
+        title: None,
+        streams,
+    };
+    let job = videoconverter::create_job_from_info(Path::new("input.mkv"), PathBuf::from("output.mkv"), &info, options).unwrap();
+
+    let cli = CliBackend { capture_output: false };
+    let recorder = RecordingBackend::new(&cli);
+    let progress = BatchProgress::new(Duration::from_secs(60), true);
+    backend::run(&recorder, job, options, &progress.start_file("input.mkv", info.duration)).unwrap();
+
+    let jobs = recorder.jobs();
+    assert_eq!(jobs.len(), 1);
+    let command = jobs[0].command.clone().expect("The ffmpeg backend planned no command");
+    return command.into_iter().map(|x| x.into_string().unwrap()).collect();
+}
+
+/// The value given for `flag`, if it is there.
+fn value<'a>(command: &'a [String], flag: &str) -> Option<&'a str> {
+    let index = command.iter().position(|x| x == flag)?;
+    return command.get(index + 1).map(String::as_str);
+}
+
+/// The input streams mapped to the output, in order.
+fn maps(command: &[String]) -> Vec<&str> {
+    command.windows(2).filter(|x| x[0] == "-map").map(|x| x[1].as_str()).collect()
+}
+
+#[test]
+fn copies_compatible_streams() {
+    let streams = vec![
+        Stream::Video(video(0, Id::H264, 1920, 1080)),
+        audio(1, Id::AAC, "eng"),
+        subtitle(2, Id::HDMV_PGS_SUBTITLE, "eng", Disposition::default()),
+    ];
+    let command = command(streams, &Options::default());
+
+    assert_eq!(command.first().map(String::as_str), Some("ffmpeg"));
+    assert_eq!(value(&command, "-loglevel"), Some("warning"));
+    assert_eq!(value(&command, "-i"), Some("input.mkv"));
+    assert_eq!(value(&command, "-default_mode"), Some("passthrough"));
+    assert_eq!(value(&command, "-c:v:0"), Some("copy"));
+    assert_eq!(value(&command, "-c:a:0"), Some("copy"));
+    assert_eq!(value(&command, "-c:s:0"), Some("copy"));
+    assert_eq!(value(&command, "-filter:v:0"), None);
+    assert_eq!(maps(&command), vec!["0:0", "0:1", "0:2"]);
+    assert_eq!(command.last().map(String::as_str), Some("output.mkv"));
+}
+
+#[test]
+fn encodes_incompatible_streams() {
+    let streams = vec![
+        Stream::Video(video(0, Id::MPEG2VIDEO, 720, 576)),
+        audio(1, Id::AC3, "eng"),
+        subtitle(2, Id::SUBRIP, "eng", Disposition::default()),
+    ];
+    let options = Options {
+        crf: 18,
+        ..Options::default()
+    };
+    let command = command(streams, &options);
+
+    assert_eq!(value(&command, "-c:v:0"), Some("libx264"));
+    assert_eq!(value(&command, "-crf:v:0"), Some("18"));
+    assert_eq!(value(&command, "-c:a:0"), Some("libfdk_aac"));
+    assert_eq!(value(&command, "-c:s:0"), Some("ass"));
+    assert_eq!(value(&command, "-filter:v:0"), None);
+    assert_eq!(maps(&command), vec!["0:0", "0:1", "0:2"]);
+}
+
+#[test]
+fn selects_and_orders_languages() {
+    let forced = Disposition {
+        forced: true,
+        ..Disposition::default()
+    };
+    let streams = vec![
+        Stream::Video(video(0, Id::H264, 1920, 1080)),
+        audio(1, Id::AAC, "fre"),
+        audio(2, Id::AAC, "eng"),
+        audio(3, Id::AAC, "jpn"),
+        subtitle(4, Id::HDMV_PGS_SUBTITLE, "eng", Disposition::default()),
+        subtitle(5, Id::HDMV_PGS_SUBTITLE, "eng", forced),
+        subtitle(6, Id::HDMV_PGS_SUBTITLE, "ger", Disposition::default()),
+    ];
+    let options = Options {
+        audio_langs: vec!["jpn".to_string(), "eng".to_string()],
+        sub_langs: vec!["eng".to_string()],
+        ..Options::default()
+    };
+    let command = command(streams, &options);
+
+    assert_eq!(maps(&command), vec!["0:0", "0:3", "0:2", "0:4", "0:5"]);
+    assert_eq!(value(&command, "-metadata:s:a:0"), Some("language=jpn"));
+    assert_eq!(value(&command, "-metadata:s:a:1"), Some("language=eng"));
+    assert_eq!(value(&command, "-metadata:s:s:1"), Some("language=eng"));
+    // The most preferred audio and the forced subtitles are played by default
+    assert_eq!(value(&command, "-disposition:v:0"), Some("default"));
+    assert_eq!(value(&command, "-disposition:a:0"), Some("default"));
+    assert_eq!(value(&command, "-disposition:a:1"), Some("0"));
+    assert_eq!(value(&command, "-disposition:s:0"), Some("0"));
+    assert_eq!(value(&command, "-disposition:s:1"), Some("default+forced"));
+}
+
+#[test]
+fn crops_tonemaps_and_scales_video() {
+    let hdr = Video {
+        pix_fmt: Some("yuv420p10le".to_string()),
+        bit_depth: Some(10),
+        color_primaries: Some("bt2020".to_string()),
+        color_transfer: Some("smpte2084".to_string()),
+        color_space: Some("bt2020nc".to_string()),
+        ..video(0, Id::HEVC, 3840, 2160)
+    };
+    let options = Options {
+        crop: Some("crop=3840:1600:0:280".to_string()),
+        max_height: Some(1080),
+        tonemap: Some(Tonemap::Replace),
+        ..Options::default()
+    };
+    let command = command(vec![Stream::Video(hdr), audio(1, Id::AAC, "eng")], &options);
+
+    assert_eq!(value(&command, "-c:v:0"), Some("libx264"));
+    let filter = value(&command, "-filter:v:0").expect("No video filters");
+    assert!(filter.starts_with("crop=3840:1600:0:280,"), "{}", filter);
+    assert!(filter.contains("tonemap=tonemap=hable"), "{}", filter);
+    // The cropped video is scaled to fit, rather than the whole frame
+    assert!(filter.ends_with(",scale=2592:1080:flags=bicubic"), "{}", filter);
+    assert_eq!(value(&command, "-pix_fmt:v:0"), Some("yuv420p"));
+    assert_eq!(value(&command, "-color_trc:v:0"), Some("bt709"));
+    assert_eq!(value(&command, "-c:a:0"), Some("copy"));
+}
+
+#[test]
+fn copies_video_that_fits_once_cropped() {
+    let options = Options {
+        crop: Some("crop=1920:800:0:140".to_string()),
+        max_height: Some(800),
+        ..Options::default()
+    };
+    let command = command(vec![Stream::Video(video(0, Id::H264, 1920, 1080))], &options);
+
+    assert_eq!(value(&command, "-c:v:0"), Some("copy"));
+    assert_eq!(value(&command, "-filter:v:0"), None);
+}
+
+#[test]
+fn filters_each_video_stream_to_suit_it() {
+    let streams = vec![
+        Stream::Video(video(0, Id::MPEG2VIDEO, 1920, 1080)),
+        Stream::Video(Video {
+            field_order: FieldOrder::Interlaced,
+            ..video(1, Id::MPEG2VIDEO, 720, 576)
+        }),
+    ];
+    let options = Options {
+        max_height: Some(720),
+        ..Options::default()
+    };
+    let command = command(streams, &options);
+
+    assert_eq!(value(&command, "-filter:v:0"), Some("scale=1280:720:flags=bicubic"));
+    assert_eq!(value(&command, "-filter:v:1"), Some("yadif"));
+}